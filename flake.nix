@@ -23,6 +23,17 @@
         rustc = rust;
       };
 
+      # stable with the wasm32 target for the browser bindings
+      rust-wasm = with fenix.packages.${system}; combine [
+        stable.cargo
+        stable.rustc
+        targets.wasm32-unknown-unknown.stable.rust-std
+      ];
+      naersk-wasm = naersk.lib."${system}".override {
+        cargo = rust-wasm;
+        rustc = rust-wasm;
+      };
+
       telegramsDump = pkgs.fetchurl {
         url = "https://files.dvb.solutions/mapping-run-20220507/telegrams-20220507-2205.csv";
         sha256 = "1hj4hx0p5qrh2p8zm6sbxdbyj2y77xl1wmb7pbpf9y206jhb2fqz";
@@ -31,16 +42,8 @@
       # `nix build`
       packages.runalyzer = naersk-lib.buildPackage {
         pname = "runalyzer";
-        src = ./runalyzer;
-        overrideMain = attrs: {
-          patchPhase = ''
-            substituteInPlace src/main.rs \
-              --replace ../stops.json ${./stops.json} \
-              --replace ../trams.json ${./trams.json} \
-              --replace ../buses.json ${./buses.json} \
-              --replace ../formatted.csv ${telegramsDump}
-          '';
-        };
+        src = ./.;
+        cargoBuildOptions = x: x ++ [ "-p" "runalyzer" ];
         doCheck = true;
         cargoTestCommands = x:
           x ++ [
@@ -52,12 +55,29 @@
               -A clippy::too-many-lines''
           ];
       };
+      packages.graph-generator = naersk-lib.buildPackage {
+        pname = "graphgenerator";
+        src = ./.;
+        cargoBuildOptions = x: x ++ [ "-p" "graphgenerator" ];
+        doCheck = true;
+      };
+      packages.stop-server = naersk-lib.buildPackage {
+        pname = "stopserver";
+        src = ./.;
+        cargoBuildOptions = x: x ++ [ "-p" "stopserver" ];
+        doCheck = true;
+      };
       packages.line-info = pkgs.runCommandNoCC "line-info" {
         buildInputs = [ packages.runalyzer ];
       } ''
         mkdir $out
-        cd $out
-        runalyzer
+        runalyzer segmentize \
+          --stops ${./stops.json} \
+          --telegrams ${telegramsDump} \
+          --graph ${./graph.json} \
+          --osm ${./trams.json} \
+          --osm ${./buses.json} \
+          --output-dir $out
       '';
 
       packages.stops = pkgs.stdenv.mkDerivation {
@@ -67,16 +87,42 @@
           mkdir -p $out/json
           cp stops.json $out/json/
           cp graph.json $out/json/
+          cp receivers.json $out/json/
         '';
       };
 
       packages.default = packages.line-info;
-      checks = packages;
+      checks = packages // {
+        # the library without std
+        stop-names-no-std = naersk-lib.buildPackage {
+          pname = "stop-names";
+          src = ./.;
+          cargoBuildOptions = x: x ++ [ "-p" "stop-names" "--no-default-features" ];
+          copyLibs = true;
+        };
+        # the wasm module, stop-names itself is only an rlib
+        stop-names-wasm = naersk-wasm.buildPackage {
+          pname = "stop-names";
+          src = ./.;
+          CARGO_BUILD_TARGET = "wasm32-unknown-unknown";
+          cargoBuild = ''cargo $cargo_options rustc $cargo_build_options --lib --crate-type cdylib >> $cargo_build_output_json'';
+          cargoBuildOptions = x: x ++ [ "-p" "stop-names" "--no-default-features" "--features" "std,wasm" ];
+          copyLibs = true;
+        };
+      };
 
       # `nix run`
       apps.runalyzer = utils.lib.mkApp {
         drv = packages.runalyzer;
       };
+      apps.graph-generator = utils.lib.mkApp {
+        drv = packages.graph-generator;
+        exePath = "/bin/graphgenerator";
+      };
+      apps.stop-server = utils.lib.mkApp {
+        drv = packages.stop-server;
+        exePath = "/bin/stopserver";
+      };
       apps.default = apps.runalyzer;
 
       # `nix develop`
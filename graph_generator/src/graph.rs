@@ -0,0 +1,94 @@
+use std::collections::{BTreeSet, HashMap};
+use chrono::Duration;
+use stop_names::{Edge, RegionGraph};
+use crate::telegram::{Direction, Junction, Telegram};
+
+/// default time after a telegram in which the junctions reported by the same
+/// vehicle are considered as its successors
+pub const LOOK_AHEAD_SECONDS: i64 = 300;
+
+/// A vehicle reporting a junction within the look ahead after a telegram.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    pub junction: Junction,
+    pub travel_time: Duration,
+    pub line: u16,
+}
+
+/// Every other junction reported by the same vehicle within `look_ahead`
+/// after each telegram, grouped by junction and direction of the telegram.
+/// The junction observed most often is the successor, like in the graphs
+/// with a single successor. `telegrams` must be ordered by time.
+pub fn measure(telegrams: &[Telegram], look_ahead: Duration) -> HashMap<(Junction, Direction), Vec<Observation>> {
+    let mut measured: HashMap<(Junction, Direction), Vec<Observation>> = HashMap::new();
+
+    for (i, current_tele) in telegrams.iter().enumerate() {
+        let time_limit = current_tele.time + look_ahead;
+        let futures = telegrams[i + 1..].iter()
+            .take_while(|future_tele| future_tele.time < time_limit)
+            .filter(|future_tele| {
+                current_tele.line == future_tele.line &&
+                current_tele.run_number == future_tele.run_number &&
+                current_tele.junction != future_tele.junction
+            })
+            .map(|future_tele| Observation {
+                junction: future_tele.junction,
+                travel_time: future_tele.time - current_tele.time,
+                line: current_tele.line.number,
+            });
+
+        measured.entry((current_tele.junction, current_tele.direction_request))
+            .or_default()
+            .extend(futures);
+    }
+
+    measured
+}
+
+/// median of the travel times in seconds
+fn median(mut travel_times: Vec<f64>) -> Option<f64> {
+    travel_times.sort_by(f64::total_cmp);
+    let middle = travel_times.len() / 2;
+    match travel_times.len() {
+        0 => None,
+        length if length % 2 == 0 => Some((travel_times[middle - 1] + travel_times[middle]) / 2.0),
+        _ => Some(travel_times[middle]),
+    }
+}
+
+/// One edge per observed junction, the most frequent first and the lowest
+/// junction first on ties.
+pub fn collect_edges(observations: &[Observation]) -> Vec<Edge> {
+    let mut by_junction: HashMap<Junction, (Vec<f64>, BTreeSet<u16>)> = HashMap::new();
+    for observation in observations {
+        let (travel_times, lines) = by_junction.entry(observation.junction).or_default();
+        travel_times.push(observation.travel_time.num_milliseconds() as f64 / 1000.0);
+        lines.insert(observation.line);
+    }
+
+    let mut edges = by_junction.into_iter()
+        .map(|(junction, (travel_times, lines))| Edge {
+            target: junction.0,
+            count: travel_times.len() as u32,
+            median_travel_time: median(travel_times),
+            lines: lines.into_iter().collect(),
+        })
+        .collect::<Vec<Edge>>();
+    edges.sort_by_key(|edge| (std::cmp::Reverse(edge.count), edge.target));
+    edges
+}
+
+/// Every junction observed after every junction and direction, counted
+/// once per telegram it follows.
+pub fn build_graph(telegrams: &[Telegram], look_ahead: Duration) -> RegionGraph {
+    let mut graph = RegionGraph::default();
+    for ((junction, direction), observations) in measure(telegrams, look_ahead) {
+        if observations.is_empty() {
+            continue;
+        }
+        graph.structure.entry(junction.0)
+            .or_default()
+            .insert(direction.0, collect_edges(&observations));
+    }
+    graph
+}
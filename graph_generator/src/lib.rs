@@ -0,0 +1,5 @@
+pub mod telegram;
+pub mod graph;
+
+#[cfg(test)]
+mod tests;
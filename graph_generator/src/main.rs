@@ -1,97 +1,66 @@
-#![feature(drain_filter)]
-
-pub mod telegram;
-
-
-use telegram::{read_telegrams, Junction, Direction};
-use std::collections::HashMap;
-use serde_json;
-use std::fs::File;
-use std::io::Write;
-
-fn index_of_max(values: &[u32]) -> Option<usize> {
-    values
-        .iter()
-        .enumerate()
-        .max_by_key(|(_idx, &val)| val)
-        .map(|(idx, _val)| idx)
-}
-
-
-fn choose_junction(vec: Vec<Junction>) -> Option<Junction> {
-    let mut occurences: HashMap<Junction, u32> = HashMap::new();
-    for element in vec {
-        if occurences.contains_key(&element) {
-            *occurences.get_mut(&element).unwrap() += 1;
-        } else {
-            occurences.insert(element, 1);
-        }
-    }
-
-    let values: Vec<u32> = occurences.clone().into_values().collect();
-    let keys: Vec<Junction> = occurences.into_keys().collect();
-
-    match index_of_max(values.as_slice()) {
-        Some(index) => {
-            Some(keys[index])
-        }
-        None => {
-            None
-        }
-    }
+use std::error::Error;
+use chrono::Duration;
+use clap::Parser;
+use stop_names::{InterRegionalGraph, ReceiverRegistry};
+use graphgenerator::graph::{build_graph, LOOK_AHEAD_SECONDS};
+use graphgenerator::telegram::{read_telegrams, ReceiverFilter};
+
+/// Builds graph.json, the observed successors of every reporting point and
+/// direction, from a telegram dump.
+#[derive(Debug, Parser)]
+#[command(name = "graphgenerator", version)]
+struct Cli {
+    /// telegram dump in csv format
+    #[arg(long, default_value = "formatted.csv")]
+    telegrams: String,
+    /// graph.json to write
+    #[arg(long, default_value = "graph.json")]
+    output: String,
+    /// region id the graph is stored under
+    #[arg(long, default_value_t = 0)]
+    region: u32,
+    /// use the receivers of this registry that belong to the region
+    #[arg(long, default_value = "receivers.json")]
+    receivers: String,
+    /// also use this receiver ip or station id, can be given multiple times
+    #[arg(long = "receiver")]
+    receiver: Vec<String>,
+    /// use the telegrams of every receiver instead of the registry
+    #[arg(long, conflicts_with_all = ["receivers", "receiver"])]
+    all_receivers: bool,
+    /// seconds after a telegram in which the next junction of the same vehicle
+    /// counts as its successor
+    #[arg(long, default_value_t = LOOK_AHEAD_SECONDS)]
+    look_ahead: i64,
 }
 
-fn main() {
+fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
     println!("Starting Script ... ");
 
-    let path: String = String::from("./formatted.csv");
-    let telegrams = read_telegrams(&path).unwrap();
-    let mut graph: HashMap<Junction, HashMap<Direction, Junction>> = HashMap::new();
-    let mut measured: HashMap<(Junction, Direction), Vec<Junction>> = HashMap::new();
-    let time_limit_future: u64 = 300;
-
-    for i in 0..telegrams.len() - 1 {
-        let current_tele = &telegrams[i];
-
-        let mut time = current_tele.time;
-        let mut iterator = i + 10;
-        while (time.timestamp() as u64) < current_tele.time.timestamp() as u64 + time_limit_future &&  iterator < telegrams.len() {
-            let future_tele = &telegrams[iterator];
-            time = future_tele.time;
-
-            if current_tele.line == future_tele.line && current_tele.run_number == future_tele.run_number {
-                match measured.get_mut(&(current_tele.junction, current_tele.direction_request)) {
-                    Some(value) => {
-                        value.push(future_tele.junction);
-                    }
-                    None => {
-                        measured.insert((current_tele.junction, current_tele.direction_request), vec![future_tele.junction]);
-                    }
-                }
-            }
-
-            iterator += 1
-        }
-    }
-
-    for (key, mut value) in measured {
-        let drained: Vec<Junction> = value.drain_filter(|&mut e| e != key.0).collect();
-        match choose_junction(drained) {
-            Some(target_junction) => {
-                match graph.get_mut(&key.0) {
-                    Some(data) => {
-                        data.insert(key.1, target_junction);
-                    }
-                    None => {
-                        graph.insert(key.0, HashMap::from([(key.1, target_junction)]));
-                    }
-                }
-            }
-            None => {}
-        };
+    let filter = if cli.all_receivers {
+        ReceiverFilter::all()
+    } else {
+        let registry = ReceiverRegistry::from(&cli.receivers)
+            .ok_or_else(|| format!("cannot read receivers from {}", cli.receivers))?;
+        let mut filter = ReceiverFilter::new(cli.receiver);
+        filter.add_registry(&registry, cli.region);
+        filter
+    };
+
+    let dump = read_telegrams(&cli.telegrams, &filter)?;
+    for error in &dump.errors {
+        eprintln!("Parse error: {}", error);
     }
-    let j = serde_json::to_string_pretty(&graph).unwrap();
-    let mut output = File::create("./out.json").unwrap();
-    output.write_all(j.as_bytes()).unwrap();
+    println!(
+        "{}: parsed {} telegrams, {} from accepted receivers, {} errors",
+        cli.telegrams, dump.parsed, dump.telegrams.len(), dump.errors.len(),
+    );
+    let graph = build_graph(&dump.telegrams, Duration::seconds(cli.look_ahead));
+    println!("{} junctions with successors", graph.structure.len());
+
+    InterRegionalGraph {
+        regions: [(cli.region, graph)].into(),
+    }.write(&cli.output);
+    Ok(())
 }
-
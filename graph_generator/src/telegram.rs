@@ -1,18 +1,14 @@
+use std::collections::HashSet;
 use std::error::Error;
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use stop_names::{Line, ReceiverRegistry, Run};
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
-pub struct Line(u16);
+pub struct Junction(pub u32);
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
-pub struct Run(u16);
-
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
-pub struct Junction(u32);
-
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
-pub struct Direction(u32);
+pub struct Direction(pub u8);
 
 
 #[derive(Debug, Clone, Deserialize)]
@@ -33,28 +29,72 @@ pub struct Telegram {
     // junction_number: u16,
 }
 
-pub fn read_telegrams(path: &str) -> Result<Vec<Telegram>, Box<dyn Error>> {
-    let mut amount = 0;
-    let mut errors = 0;
-    let mut results = vec![];
+/// Ips or station ids of the receivers whose telegrams are used, `None`
+/// accepts every receiver.
+#[derive(Debug, Clone)]
+pub struct ReceiverFilter {
+    stations: Option<HashSet<String>>,
+}
+
+impl ReceiverFilter {
+    pub fn new(stations: impl IntoIterator<Item = String>) -> ReceiverFilter {
+        ReceiverFilter {
+            stations: Some(stations.into_iter().collect()),
+        }
+    }
+
+    /// accepts the telegrams of every receiver
+    pub fn all() -> ReceiverFilter {
+        ReceiverFilter { stations: None }
+    }
+
+    /// adds the receivers of the registry that belong to `region`, or are
+    /// not assigned to any region
+    pub fn add_registry(&mut self, registry: &ReceiverRegistry, region: u32) {
+        let Some(stations) = &mut self.stations else {
+            return;
+        };
+        for receiver in &registry.receivers {
+            if receiver.region.is_some_and(|receiver_region| receiver_region != region) {
+                continue;
+            }
+            stations.extend(receiver.ip.iter().cloned());
+            stations.extend(receiver.station_id.iter().cloned());
+        }
+    }
+
+    pub fn contains(&self, station: &str) -> bool {
+        self.stations.as_ref().is_none_or(|stations| stations.contains(station))
+    }
+}
+
+/// The telegrams of a dump from the accepted receivers.
+#[derive(Debug, Default)]
+pub struct Dump {
+    /// ordered by time
+    pub telegrams: Vec<Telegram>,
+    /// number of parsed telegrams, including those of other receivers
+    pub parsed: usize,
+    /// the rows that are no telegrams
+    pub errors: Vec<csv::Error>,
+}
 
-    let desired_station = vec![String::from("10.13.37.100"), String::from("10.13.37.101")];
+/// reads the telegrams of the accepted receivers
+pub fn read_telegrams(path: &str, filter: &ReceiverFilter) -> Result<Dump, Box<dyn Error>> {
+    let mut dump = Dump::default();
 
     for result in csv::Reader::from_path(path)?.deserialize::<Telegram>() {
         match result {
-            Err(e) => {
-                eprintln!("Parse error: {}", e);
-                errors += 1;
-            }
+            Err(e) => dump.errors.push(e),
             Ok(telegram) => {
-                if desired_station.contains(&telegram.ip) {
-                    results.push(telegram);
-                    amount += 1
+                dump.parsed += 1;
+                if filter.contains(&telegram.ip) {
+                    dump.telegrams.push(telegram);
                 }
             }
         }
     }
+    dump.telegrams.sort_by_key(|telegram| telegram.time);
 
-    println!("{}: parsed {} telegrams into {} line runs, {} errors", path, amount, results.len(), errors);
-    Ok(results)
+    Ok(dump)
 }
@@ -0,0 +1,99 @@
+use chrono::Duration;
+use stop_names::{Edge, HashMap, Receiver, ReceiverRegistry, RegionGraph};
+use crate::graph::{build_graph, collect_edges, Observation, LOOK_AHEAD_SECONDS};
+use crate::telegram::{read_telegrams, Junction, ReceiverFilter};
+
+const TELEGRAMS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/telegrams.csv");
+
+fn receiver(id: u32, ip: &str, region: u32) -> Receiver {
+    Receiver {
+        id,
+        name: None,
+        ip: Some(ip.to_string()),
+        station_id: None,
+        region: Some(region),
+        lat: None,
+        lon: None,
+    }
+}
+
+fn observation(junction: u32, seconds: i64, line: u16) -> Observation {
+    Observation {
+        junction: Junction(junction),
+        travel_time: Duration::seconds(seconds),
+        line,
+    }
+}
+
+/// the most likely successor of every reporting point and direction
+fn successors(graph: &RegionGraph) -> HashMap<(u32, u8), u32> {
+    graph.structure.iter()
+        .flat_map(|(reporting_point, successors)| successors.keys()
+            .map(move |direction| (*reporting_point, *direction)))
+        .filter_map(|(reporting_point, direction)| graph.most_likely_successor(&reporting_point, &direction)
+            .map(|target| ((reporting_point, direction), target)))
+        .collect()
+}
+
+#[test]
+fn test_collect_edges() {
+    let edges = collect_edges(&[
+        observation(2, 30, 3),
+        observation(1, 40, 3),
+        observation(2, 50, 7),
+        observation(2, 20, 3),
+        observation(3, 10, 3),
+    ]);
+    assert_eq!(edges, vec![
+        Edge { target: 2, count: 3, median_travel_time: Some(30.0), lines: vec![3, 7] },
+        // ties go to the lowest junction
+        Edge { target: 1, count: 1, median_travel_time: Some(40.0), lines: vec![3] },
+        Edge { target: 3, count: 1, median_travel_time: Some(10.0), lines: vec![3] },
+    ]);
+    assert!(collect_edges(&[]).is_empty());
+}
+
+#[test]
+fn test_build_graph() {
+    let mut filter = ReceiverFilter::new([]);
+    filter.add_registry(&ReceiverRegistry {
+        receivers: vec![
+            receiver(0, "10.13.37.100", 0),
+            receiver(1, "10.13.37.101", 0),
+            receiver(2, "10.13.37.102", 1),
+        ],
+    }, 0);
+    let dump = read_telegrams(TELEGRAMS, &filter).expect("cannot read telegrams");
+    // the last row is no telegram
+    assert_eq!((dump.parsed, dump.errors.len()), (10, 1));
+    let telegrams = dump.telegrams;
+    assert_eq!(telegrams.len(), 8);
+    // the dump is not ordered by time
+    assert!(telegrams.windows(2).all(|pair| pair[0].time <= pair[1].time));
+
+    let graph = build_graph(&telegrams, Duration::seconds(LOOK_AHEAD_SECONDS));
+    assert_eq!(graph.structure, HashMap::from([
+        (100, HashMap::from([(1, vec![
+            Edge { target: 150, count: 4, median_travel_time: Some(60.0), lines: vec![3] },
+            Edge { target: 160, count: 2, median_travel_time: Some(119.5), lines: vec![3] },
+            Edge { target: 400, count: 1, median_travel_time: Some(50.0), lines: vec![7] },
+        ])])),
+        (150, HashMap::from([(1, vec![
+            Edge { target: 160, count: 2, median_travel_time: Some(59.5), lines: vec![3] },
+        ])])),
+    ]));
+    // the single successor graph of graphgenerator 0.2.0 for the same dump
+    assert_eq!(successors(&graph), HashMap::from([((100, 1), 150), ((150, 1), 160)]));
+
+    // the bit errors of receiver .102 outweigh the real successor
+    let telegrams = read_telegrams(TELEGRAMS, &ReceiverFilter::all()).expect("cannot read telegrams").telegrams;
+    let graph = build_graph(&telegrams, Duration::seconds(LOOK_AHEAD_SECONDS));
+    assert_eq!(successors(&graph), HashMap::from([((100, 1), 150), ((150, 1), 999), ((999, 1), 160)]));
+
+    // nothing follows within ten seconds
+    let telegrams = read_telegrams(TELEGRAMS, &ReceiverFilter::new(["10.13.37.100".to_string()])).expect("cannot read telegrams").telegrams;
+    assert!(build_graph(&telegrams, Duration::seconds(10)).structure.is_empty());
+
+    // a filter without receivers accepts no telegram
+    assert!(read_telegrams(TELEGRAMS, &ReceiverFilter::new([])).expect("cannot read telegrams").telegrams.is_empty());
+}
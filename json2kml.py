@@ -1,16 +1,17 @@
 #!/usr/bin/env python3
 
-import json
 import simplekml
+import stop_names
 
-with open('./stops.json', 'r') as f:
-    stops = json.load(f)['0']
+stops = stop_names.Stops.load('./stops.json')
 
 kml = simplekml.Kml()
 kml.document.name = "Telegram Locations"
 
-for id in stops.keys():
-    kml.newpoint(name = str(id), coords = [( stops[id]['lon'], stops[id]['lat'] )])
+for id in sorted(stops.positions(0)):
+    position = stops.approximate_position(0, id)
+    if position.status == 'unknown':
+        continue
+    kml.newpoint(name = str(id), coords = [( position.lon, position.lat )])
 
 kml.save(path = './stops.kml')
-
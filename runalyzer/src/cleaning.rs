@@ -0,0 +1,261 @@
+//! Cleans a telegram dump before it is split into runs. Dumps contain the
+//! same telegram from several receivers, bit errors and timestamps that are
+//! slightly out of order.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use geo::{prelude::HaversineDistance, Point};
+use serde::Serialize;
+use stop_names::RegionGraph;
+use super::known_stops::Stop;
+use super::telegram::Telegram;
+use super::{Junction, Run};
+
+#[derive(Debug, Clone)]
+pub struct CleaningConfig {
+    /// the same telegram received again within this window is a duplicate
+    pub dedup_window: Duration,
+    /// telegrams at most this much older than the latest one are reordered,
+    /// older ones are dropped
+    pub reorder_tolerance: Duration,
+    /// maximum speed in m/s between two junctions with known positions
+    pub max_speed: f64,
+    /// maximum number of graph edges between two consecutive junctions
+    pub max_hops: usize,
+    /// after this many unreachable or too fast junctions in a row the vehicle
+    /// is assumed to have left the known graph, or the last accepted junction
+    /// to be a bit error, and the next one is accepted
+    pub max_unreachable: usize,
+    /// consecutive telegrams further apart are not compared
+    pub max_gap: Duration,
+}
+
+impl Default for CleaningConfig {
+    fn default() -> Self {
+        CleaningConfig {
+            dedup_window: Duration::from_secs(10),
+            reorder_tolerance: Duration::from_secs(30),
+            max_speed: 30.0,
+            max_hops: 10,
+            max_unreachable: 3,
+            max_gap: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Number of dropped telegrams per reason.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Rejections {
+    pub out_of_order: usize,
+    pub duplicate: usize,
+    pub unreachable: usize,
+    pub impossible_speed: usize,
+}
+
+impl Rejections {
+    pub fn total(&self) -> usize {
+        self.out_of_order + self.duplicate + self.unreachable + self.impossible_speed
+    }
+}
+
+impl fmt::Display for Rejections {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} out of order, {} duplicates, {} unreachable, {} impossible speed",
+            self.out_of_order, self.duplicate, self.unreachable, self.impossible_speed,
+        )
+    }
+}
+
+/// Runs all cleaning steps. `graph` and `positions` are optional, without
+/// them the reachability and speed checks are skipped.
+pub fn clean(
+    telegrams: Vec<Telegram>,
+    config: &CleaningConfig,
+    graph: Option<&RegionGraph>,
+    positions: &HashMap<Junction, Stop>,
+) -> (Vec<Telegram>, Rejections) {
+    let mut rejections = Rejections::default();
+    let telegrams = reorder(telegrams, config.reorder_tolerance, &mut rejections);
+    let telegrams = dedup(telegrams, config.dedup_window, &mut rejections);
+    let telegrams = check_plausibility(telegrams, config, graph, positions, &mut rejections);
+    (telegrams, rejections)
+}
+
+/// Sorts the telegrams by time, dropping those that arrive later than the
+/// tolerance allows.
+pub fn reorder(telegrams: Vec<Telegram>, tolerance: Duration, rejections: &mut Rejections) -> Vec<Telegram> {
+    let mut latest = None;
+    let mut results = Vec::with_capacity(telegrams.len());
+    for telegram in telegrams {
+        if latest.is_some_and(|latest| telegram.time + tolerance < latest) {
+            rejections.out_of_order += 1;
+            continue;
+        }
+        if latest.is_none_or(|latest| latest < telegram.time) {
+            latest = Some(telegram.time);
+        }
+        results.push(telegram);
+    }
+    results.sort_by_key(|telegram| telegram.time);
+    results
+}
+
+/// Drops telegrams of a vehicle at a junction that has been received within
+/// the window, no matter by which receiver.
+pub fn dedup(telegrams: Vec<Telegram>, window: Duration, rejections: &mut Rejections) -> Vec<Telegram> {
+    let mut filter = OutlierFilter::new(CleaningConfig {
+        dedup_window: window,
+        ..Default::default()
+    });
+    telegrams.into_iter()
+        .filter(|telegram| !filter.is_duplicate(telegram, rejections))
+        .collect()
+}
+
+/// whether `to` can be reached from `from` within `max_hops` graph edges
+pub fn is_reachable(graph: &RegionGraph, from: Junction, to: Junction, max_hops: usize) -> bool {
+    let mut visited = HashSet::from([from.0]);
+    let mut queue = VecDeque::from([(from.0, 0)]);
+    while let Some((junction, hops)) = queue.pop_front() {
+        if junction == to.0 {
+            return true;
+        }
+        if hops == max_hops {
+            continue;
+        }
+        for next in graph.neighbours(&junction) {
+            if visited.insert(next) {
+                queue.push_back((next, hops + 1));
+            }
+        }
+    }
+    false
+}
+
+/// Compares every telegram with the last accepted one of its vehicle.
+fn check_plausibility(
+    telegrams: Vec<Telegram>,
+    config: &CleaningConfig,
+    graph: Option<&RegionGraph>,
+    positions: &HashMap<Junction, Stop>,
+    rejections: &mut Rejections,
+) -> Vec<Telegram> {
+    let mut filter = OutlierFilter::new(config.clone());
+    telegrams.into_iter()
+        .filter(|telegram| filter.is_plausible(telegram, graph, positions, rejections))
+        .collect()
+}
+
+/// Duplicate and plausibility checks for a stream of telegrams that arrive
+/// in order, like the live feed.
+#[derive(Debug, Clone, Default)]
+pub struct OutlierFilter {
+    config: CleaningConfig,
+    /// first reception of every telegram within the dedup window
+    last_seen: HashMap<(u16, Run, Junction, Option<u8>, Option<u64>), SystemTime>,
+    /// last accepted telegram and rejections since then per vehicle
+    last: HashMap<(u16, Run), (Telegram, usize)>,
+    /// time of the newest telegram when the entries above were last pruned
+    pruned: Option<SystemTime>,
+}
+
+impl OutlierFilter {
+    pub fn new(config: CleaningConfig) -> Self {
+        OutlierFilter {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// number of telegrams and vehicles the filter remembers
+    pub fn remembered(&self) -> usize {
+        self.last_seen.len() + self.last.len()
+    }
+
+    /// Forgets receptions older than the dedup window and vehicles that have
+    /// been silent for longer than `max_gap`, as they no longer change the
+    /// result. Runs at most once per dedup window.
+    fn prune(&mut self, now: SystemTime) {
+        if self.pruned.is_some_and(|pruned| now < pruned + self.config.dedup_window) {
+            return;
+        }
+        let (dedup_window, max_gap) = (self.config.dedup_window, self.config.max_gap);
+        let age = |time: SystemTime| now.duration_since(time).unwrap_or_default();
+        self.last_seen.retain(|_, seen| age(*seen) <= dedup_window);
+        self.last.retain(|_, (telegram, _)| age(telegram.time) <= max_gap);
+        self.pruned = Some(now);
+    }
+
+    /// whether the telegram has already been received within the window
+    pub fn is_duplicate(&mut self, telegram: &Telegram, rejections: &mut Rejections) -> bool {
+        self.prune(telegram.time);
+        let key = (telegram.line, telegram.run, telegram.junction, telegram.direction_request, telegram.destination_number);
+        match self.last_seen.insert(key, telegram.time) {
+            Some(last) if telegram.time.duration_since(last).unwrap_or_default() <= self.config.dedup_window => {
+                // keep the window anchored at the first reception
+                self.last_seen.insert(key, last);
+                rejections.duplicate += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Compares the telegram with the last accepted one of its vehicle and
+    /// accepts it if the vehicle can have got there.
+    pub fn is_plausible(
+        &mut self,
+        telegram: &Telegram,
+        graph: Option<&RegionGraph>,
+        positions: &HashMap<Junction, Stop>,
+        rejections: &mut Rejections,
+    ) -> bool {
+        self.prune(telegram.time);
+        let config = &self.config;
+        let vehicle = (telegram.line, telegram.run);
+        if let Some((previous, rejected)) = self.last.get_mut(&vehicle) {
+            let elapsed = telegram.time.duration_since(previous.time).unwrap_or_default();
+            if previous.junction != telegram.junction && elapsed <= config.max_gap {
+                // only junctions the graph knows successors of can be checked
+                let reachable = graph.is_none_or(|graph| {
+                    graph.successors(&previous.junction.0).is_none() ||
+                    is_reachable(graph, previous.junction, telegram.junction, config.max_hops)
+                });
+                if !reachable && *rejected + 1 < config.max_unreachable {
+                    *rejected += 1;
+                    rejections.unreachable += 1;
+                    return false;
+                }
+
+                let speed = positions.get(&previous.junction)
+                    .zip(positions.get(&telegram.junction))
+                    .map(|(from, to)| {
+                        let distance = Point::new(from.lon, from.lat)
+                            .haversine_distance(&Point::new(to.lon, to.lat));
+                        distance / elapsed.as_secs_f64().max(1.0)
+                    });
+                if speed.is_some_and(|speed| speed > config.max_speed) && *rejected + 1 < config.max_unreachable {
+                    *rejected += 1;
+                    rejections.impossible_speed += 1;
+                    return false;
+                }
+            }
+        }
+
+        self.last.insert(vehicle, (telegram.clone(), 0));
+        true
+    }
+
+    /// duplicate check followed by the plausibility check
+    pub fn accept(
+        &mut self,
+        telegram: &Telegram,
+        graph: Option<&RegionGraph>,
+        positions: &HashMap<Junction, Stop>,
+        rejections: &mut Rejections,
+    ) -> bool {
+        !self.is_duplicate(telegram, rejections) && self.is_plausible(telegram, graph, positions, rejections)
+    }
+}
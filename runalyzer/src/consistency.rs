@@ -0,0 +1,185 @@
+//! Checks graph.json against a telegram dump and stops.json and reports what
+//! the data maintainers should look at.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::time::Duration;
+use geo::{prelude::HaversineDistance, Point};
+use serde::Serialize;
+use stop_names::RegionGraph;
+use super::known_stops::Stop;
+use super::telegram::RunTelegram;
+use super::{Junction, LineRun};
+
+/// An edge of graph.json.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+pub struct GraphEdge {
+    pub from: Junction,
+    pub direction: u8,
+    pub to: Junction,
+}
+
+/// A vehicle reporting `to` right after `from`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Transition {
+    pub from: Junction,
+    pub to: Junction,
+    /// direction requests reported at `from`
+    pub directions: Vec<u8>,
+    pub count: usize,
+}
+
+/// An edge the vehicles would have to travel faster than possible.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImplausibleEdge {
+    pub from: Junction,
+    pub to: Junction,
+    /// haversine distance in metres
+    pub distance: f64,
+    /// fastest observed travel time in seconds, the median of graph.json if
+    /// the edge was never observed
+    pub travel_time: f64,
+    /// speed in m/s
+    pub speed: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Report {
+    /// edges of graph.json no vehicle in the dump drove along
+    pub unobserved_edges: Vec<GraphEdge>,
+    /// transitions of the dump graph.json has no edge for
+    pub missing_edges: Vec<Transition>,
+    /// junctions of graph.json without a stops.json entry
+    pub unknown_junctions: Vec<Junction>,
+    /// junctions of graph.json whose stops.json entry has no coordinates
+    pub unlocated_junctions: Vec<Junction>,
+    pub implausible_edges: Vec<ImplausibleEdge>,
+}
+
+impl Report {
+    pub fn is_empty(&self) -> bool {
+        self.unobserved_edges.is_empty()
+            && self.missing_edges.is_empty()
+            && self.unknown_junctions.is_empty()
+            && self.unlocated_junctions.is_empty()
+            && self.implausible_edges.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} unobserved edges, {} missing edges, {} unknown junctions, {} junctions without coordinates, {} implausible edges",
+            self.unobserved_edges.len(), self.missing_edges.len(), self.unknown_junctions.len(),
+            self.unlocated_junctions.len(), self.implausible_edges.len(),
+        )
+    }
+}
+
+/// Consecutive distinct junctions of every run with their direction
+/// requests and fastest travel time. Transitions after more than `max_gap`
+/// of silence are ignored.
+fn transitions(
+    runs: &[(LineRun, Vec<RunTelegram>)],
+    max_gap: Duration,
+) -> BTreeMap<(Junction, Junction), (BTreeSet<u8>, usize, Duration)> {
+    let mut transitions = BTreeMap::<(Junction, Junction), (BTreeSet<u8>, usize, Duration)>::new();
+    for (_, telegrams) in runs {
+        for pair in telegrams.windows(2) {
+            let elapsed = pair[1].time.duration_since(pair[0].time).unwrap_or_default();
+            if pair[0].junction == pair[1].junction || elapsed > max_gap {
+                continue;
+            }
+            let (directions, count, fastest) = transitions.entry((pair[0].junction, pair[1].junction))
+                .or_insert((BTreeSet::new(), 0, elapsed));
+            directions.extend(pair[0].direction_request);
+            *count += 1;
+            *fastest = (*fastest).min(elapsed);
+        }
+    }
+    transitions
+}
+
+/// Checks the graph against the runs of a dump and the known stops.
+/// `unlocated` are the junctions stops.json lists without coordinates.
+/// `max_speed` in m/s is the fastest plausible speed along an edge.
+pub fn check(
+    graph: &RegionGraph,
+    runs: &[(LineRun, Vec<RunTelegram>)],
+    stops: &HashMap<Junction, Stop>,
+    unlocated: &HashSet<Junction>,
+    max_speed: f64,
+    max_gap: Duration,
+) -> Report {
+    let observed = transitions(runs, max_gap);
+    let mut report = Report::default();
+
+    let mut edges = graph.structure.keys()
+        .flat_map(|junction| graph.edges(junction).map(|(direction, edge)| GraphEdge {
+            from: Junction(*junction),
+            direction,
+            to: Junction(edge.target),
+        }))
+        .collect::<Vec<_>>();
+    edges.sort();
+
+    report.unobserved_edges = edges.iter()
+        .filter(|edge| !observed.contains_key(&(edge.from, edge.to)))
+        .copied()
+        .collect();
+
+    report.missing_edges = observed.iter()
+        .filter(|((from, to), _)| !graph.neighbours(&from.0).contains(&to.0))
+        .map(|((from, to), (directions, count, _))| Transition {
+            from: *from,
+            to: *to,
+            directions: directions.iter().copied().collect(),
+            count: *count,
+        })
+        .collect();
+
+    let (unlocated_junctions, unknown_junctions) = edges.iter()
+        .flat_map(|edge| [edge.from, edge.to])
+        .filter(|junction| !stops.contains_key(junction))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .partition(|junction| unlocated.contains(junction));
+    report.unknown_junctions = unknown_junctions;
+    report.unlocated_junctions = unlocated_junctions;
+
+    let mut checked = BTreeSet::new();
+    for edge in &edges {
+        if !checked.insert((edge.from, edge.to)) {
+            continue;
+        }
+        let (Some(from), Some(to)) = (stops.get(&edge.from), stops.get(&edge.to)) else {
+            continue;
+        };
+        let travel_time = match observed.get(&(edge.from, edge.to)) {
+            Some((_, _, fastest)) => fastest.as_secs_f64(),
+            None => match graph.edges(&edge.from.0)
+                .filter(|(_, graph_edge)| graph_edge.target == edge.to.0)
+                .filter_map(|(_, graph_edge)| graph_edge.median_travel_time)
+                .min_by(f64::total_cmp)
+            {
+                Some(median) => median,
+                None => continue,
+            },
+        };
+
+        let distance = Point::new(from.lon, from.lat)
+            .haversine_distance(&Point::new(to.lon, to.lat));
+        let speed = distance / travel_time.max(1.0);
+        if speed > max_speed {
+            report.implausible_edges.push(ImplausibleEdge {
+                from: edge.from,
+                to: edge.to,
+                distance,
+                travel_time,
+                speed,
+            });
+        }
+    }
+
+    report
+}
@@ -0,0 +1,144 @@
+//! Which reporting points each receiver hears. A passage is one vehicle
+//! reporting at a junction, no matter how many receivers got it.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+use serde::Serialize;
+use serde_json::{json, Value};
+use stop_names::ReceiverRegistry;
+use super::known_stops::Stop;
+use super::telegram::Telegram;
+use super::{Junction, Run};
+
+/// default time in which telegrams of a vehicle at a junction count as one
+/// passage
+pub const PASSAGE_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JunctionCoverage {
+    pub junction: Junction,
+    pub telegrams: usize,
+    /// passages heard by this receiver
+    pub passages: usize,
+    /// share of all passages at the junction heard by this receiver
+    pub reliability: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReceiverCoverage {
+    /// ip or station id from the dump
+    pub receiver: String,
+    /// id in the receiver registry, `None` for unregistered receivers
+    pub id: Option<u32>,
+    pub telegrams: usize,
+    pub junctions: Vec<JunctionCoverage>,
+}
+
+/// Coverage of every receiver in the dump, ordered by receiver.
+pub fn coverage(
+    telegrams: &[Telegram],
+    registry: &ReceiverRegistry,
+    window: Duration,
+) -> Vec<ReceiverCoverage> {
+    let mut telegrams = telegrams.iter()
+        .filter(|telegram| telegram.receiver.is_some())
+        .collect::<Vec<_>>();
+    telegrams.sort_by_key(|telegram| telegram.time);
+
+    // start of the current passage per vehicle and junction
+    let mut passage_start = HashMap::<(u16, Run, Junction), (SystemTime, usize)>::new();
+    let mut passages = 0;
+    let mut passages_by_junction = HashMap::<Junction, usize>::new();
+    // telegrams and heard passages per receiver and junction
+    let mut heard = BTreeMap::<&str, BTreeMap<Junction, (usize, HashSet<usize>)>>::new();
+
+    for telegram in telegrams {
+        let key = (telegram.line, telegram.run, telegram.junction);
+        let passage = match passage_start.get(&key) {
+            Some((start, passage)) if telegram.time.duration_since(*start).unwrap_or_default() <= window => *passage,
+            _ => {
+                passages += 1;
+                passage_start.insert(key, (telegram.time, passages));
+                *passages_by_junction.entry(telegram.junction).or_default() += 1;
+                passages
+            }
+        };
+
+        let receiver = telegram.receiver.as_deref().unwrap_or_default();
+        let (count, receiver_passages) = heard.entry(receiver)
+            .or_default()
+            .entry(telegram.junction)
+            .or_default();
+        *count += 1;
+        receiver_passages.insert(passage);
+    }
+
+    heard.into_iter()
+        .map(|(receiver, junctions)| ReceiverCoverage {
+            receiver: receiver.to_string(),
+            id: registry.find(receiver).map(|receiver| receiver.id),
+            telegrams: junctions.values().map(|(count, _)| count).sum(),
+            junctions: junctions.into_iter()
+                .map(|(junction, (count, receiver_passages))| JunctionCoverage {
+                    junction,
+                    telegrams: count,
+                    passages: receiver_passages.len(),
+                    reliability: receiver_passages.len() as f64 / passages_by_junction[&junction] as f64,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// GeoJSON FeatureCollection with a Point per receiver and junction it
+/// hears, weighted by reliability, and a Point per located receiver.
+pub fn heatmap(
+    coverage: &[ReceiverCoverage],
+    registry: &ReceiverRegistry,
+    stops: &HashMap<Junction, Stop>,
+) -> Value {
+    let mut features = vec![];
+    for receiver in &registry.receivers {
+        if let (Some(lat), Some(lon)) = (receiver.lat, receiver.lon) {
+            features.push(json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": [lon, lat],
+                },
+                "properties": {
+                    "receiver": receiver.id,
+                    "name": receiver.name,
+                },
+            }));
+        }
+    }
+
+    for receiver in coverage {
+        for junction in &receiver.junctions {
+            // junctions without a position cannot be drawn
+            let Some(stop) = stops.get(&junction.junction) else {
+                continue;
+            };
+            features.push(json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": [stop.lon, stop.lat],
+                },
+                "properties": {
+                    "receiver": receiver.id,
+                    "station": receiver.receiver,
+                    "junction": junction.junction,
+                    "telegrams": junction.telegrams,
+                    "passages": junction.passages,
+                    "reliability": junction.reliability,
+                },
+            }));
+        }
+    }
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
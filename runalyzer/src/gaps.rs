@@ -0,0 +1,95 @@
+//! Reporting points a run passed while no receiver heard it. The skipped
+//! junctions are taken from the fastest route through graph.json and get
+//! timestamps in proportion to the median travel times of its edges.
+use std::time::{Duration, SystemTime};
+use serde::Serialize;
+use stop_names::{RegionGraph, Route, Weight};
+use super::Junction;
+
+/// default maximum number of junctions inferred between two reported ones
+pub const MAX_SKIPPED: usize = 5;
+
+/// A junction passed by a run, either reported or inferred from the graph.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+pub struct Passage {
+    pub time: SystemTime,
+    pub junction: Junction,
+    pub inferred: bool,
+}
+
+/// fastest route between two junctions, preferring edges observed on the line
+fn fastest_route(graph: &RegionGraph, from: Junction, to: Junction, line: Option<u16>) -> Option<(Route, Option<u16>)> {
+    line.and_then(|line| graph.shortest_path(from.0, to.0, Weight::ExpectedTime, Some(line)).map(|route| (route, Some(line))))
+        .or_else(|| graph.shortest_path(from.0, to.0, Weight::ExpectedTime, None).map(|route| (route, None)))
+}
+
+/// The junctions skipped between two consecutive reported junctions.
+pub fn skipped(
+    graph: &RegionGraph,
+    (from_time, from): (SystemTime, Junction),
+    (to_time, to): (SystemTime, Junction),
+    line: Option<u16>,
+    max_skipped: usize,
+) -> Vec<Passage> {
+    if from == to || graph.neighbours(&from.0).contains(&to.0) {
+        return vec![];
+    }
+    let Some((route, line)) = fastest_route(graph, from, to, line) else {
+        return vec![];
+    };
+    let skipped = route.between();
+    if skipped.is_empty() || skipped.len() > max_skipped {
+        return vec![];
+    }
+
+    let elapsed = to_time.duration_since(from_time).unwrap_or_default();
+    let hops = route.junctions.len() - 1;
+    let mut travelled = 0.0;
+    route.junctions.windows(2)
+        .zip(skipped)
+        .enumerate()
+        .map(|(hop, (pair, junction))| {
+            travelled += graph.step_cost(&pair[0], &pair[1], Weight::ExpectedTime, line).unwrap_or_default();
+            // edges measured with a travel time of zero are spread evenly
+            let share = if route.cost > 0.0 {
+                travelled / route.cost
+            } else {
+                (hop + 1) as f64 / hops as f64
+            };
+            Passage {
+                time: from_time + Duration::from_secs_f64(elapsed.as_secs_f64() * share),
+                junction: Junction(*junction),
+                inferred: true,
+            }
+        })
+        .collect()
+}
+
+/// The junctions of a run with the junctions it likely skipped inserted.
+/// `line` is the R09 line number of the run.
+pub fn fill_gaps(
+    junctions: &[(SystemTime, Junction)],
+    graph: &RegionGraph,
+    line: Option<u16>,
+    max_skipped: usize,
+) -> Vec<Passage> {
+    let mut passages = vec![];
+    for (i, (time, junction)) in junctions.iter().enumerate() {
+        if i > 0 {
+            passages.extend(skipped(graph, junctions[i - 1], (*time, *junction), line, max_skipped));
+        }
+        passages.push(Passage {
+            time: *time,
+            junction: *junction,
+            inferred: false,
+        });
+    }
+    passages
+}
+
+/// the passages as junctions of a run, inferred or not
+pub fn timeline(passages: &[Passage]) -> Vec<(SystemTime, Junction)> {
+    passages.iter()
+        .map(|passage| (passage.time, passage.junction))
+        .collect()
+}
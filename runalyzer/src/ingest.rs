@@ -0,0 +1,163 @@
+//! Live telegram ingest. A source pushes the decoded telegrams of the
+//! collection server into a channel, the pipeline drops outliers, enriches
+//! the rest with their stops and tracks the vehicles.
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+use futures_util::StreamExt;
+use stop_names::RegionGraph;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use super::cleaning::{CleaningConfig, OutlierFilter, Rejections};
+use super::known_stops::Stop;
+use super::telegram::{self, Telegram};
+use super::tracker::{LiveTelegram, VehicleTracker};
+use super::Junction;
+
+pub type IngestError = Box<dyn Error + Send + Sync>;
+
+/// capacity of the channels between source, pipeline and consumer
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+/// a message of a source, the error if it is no telegram
+pub type Received = Result<Telegram, IngestError>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// one json telegram per datagram
+    Udp(SocketAddr),
+    /// one json telegram per text or binary message
+    WebSocket(String),
+    /// Plays back a csv dump. `speed` is the playback factor, 1.0 is real
+    /// time, non positive or infinite speeds replay without waiting.
+    Replay { path: String, speed: f64 },
+}
+
+impl Source {
+    /// Sends the telegrams until the source ends or the receiver is dropped.
+    pub async fn run(self, sender: mpsc::Sender<Received>) -> Result<(), IngestError> {
+        match self {
+            Source::Udp(address) => udp(UdpSocket::bind(address).await?, sender).await,
+            Source::WebSocket(url) => websocket(&url, sender).await,
+            Source::Replay { path, speed } => replay(&path, speed, sender).await,
+        }
+    }
+}
+
+/// Receives telegrams on an already bound socket.
+pub async fn udp(socket: UdpSocket, sender: mpsc::Sender<Received>) -> Result<(), IngestError> {
+    let mut buffer = vec![0; 65536];
+    loop {
+        let (length, _) = socket.recv_from(&mut buffer).await?;
+        if sender.send(telegram::parse_json(&buffer[..length]).map_err(IngestError::from)).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+async fn websocket(url: &str, sender: mpsc::Sender<Received>) -> Result<(), IngestError> {
+    let (mut stream, _) = tokio_tungstenite::connect_async(url).await?;
+    while let Some(message) = stream.next().await {
+        let telegram = match message? {
+            Message::Text(text) => telegram::parse_json(text.as_bytes()),
+            Message::Binary(bytes) => telegram::parse_json(&bytes),
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        if sender.send(telegram.map_err(IngestError::from)).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn replay(path: &str, speed: f64, sender: mpsc::Sender<Received>) -> Result<(), IngestError> {
+    let (mut telegrams, errors) = telegram::read_csv(path).map_err(|e| e.to_string())?;
+    telegrams.sort_by_key(|telegram| telegram.time);
+    // broken rows count like messages that are no telegrams
+    for error in errors {
+        if sender.send(Err(error.into())).await.is_err() {
+            return Ok(());
+        }
+    }
+
+    let mut previous = None;
+    for telegram in telegrams {
+        if let Some(previous) = previous {
+            let elapsed = telegram.time.duration_since(previous).unwrap_or_default();
+            if speed > 0.0 && speed.is_finite() {
+                tokio::time::sleep(Duration::from_secs_f64(elapsed.as_secs_f64() / speed)).await;
+            }
+        }
+        previous = Some(telegram.time);
+        if sender.send(Ok(telegram)).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Outlier filter, stop enrichment and vehicle tracker of the live feed.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    stops: HashMap<Junction, Stop>,
+    graph: Option<RegionGraph>,
+    filter: OutlierFilter,
+    pub tracker: VehicleTracker,
+    pub rejections: Rejections,
+    /// messages of the source that are no telegrams
+    pub unparsable: usize,
+}
+
+impl Pipeline {
+    pub fn new(stops: HashMap<Junction, Stop>, graph: Option<RegionGraph>, config: CleaningConfig) -> Self {
+        Pipeline {
+            stops,
+            graph,
+            filter: OutlierFilter::new(config),
+            ..Default::default()
+        }
+    }
+
+    /// The enriched telegram, `None` if the outlier filter drops it.
+    pub fn process(&mut self, telegram: Telegram) -> Option<LiveTelegram> {
+        if !self.filter.accept(&telegram, self.graph.as_ref(), &self.stops, &mut self.rejections) {
+            return None;
+        }
+        let live = LiveTelegram {
+            stop: self.stops.get(&telegram.junction).cloned(),
+            telegram,
+        };
+        self.tracker.update(&live);
+        Some(live)
+    }
+}
+
+/// Runs the source through the pipeline into `sink` until the source ends
+/// or the sink is dropped, and returns the pipeline with its vehicles.
+pub async fn ingest(
+    source: Source,
+    mut pipeline: Pipeline,
+    sink: mpsc::Sender<LiveTelegram>,
+) -> Result<Pipeline, IngestError> {
+    let (sender, mut receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    let source = tokio::spawn(source.run(sender));
+
+    while let Some(received) = receiver.recv().await {
+        let Ok(telegram) = received else {
+            pipeline.unparsable += 1;
+            continue;
+        };
+        if let Some(live) = pipeline.process(telegram) {
+            if sink.send(live).await.is_err() {
+                source.abort();
+                return Ok(pipeline);
+            }
+        }
+    }
+
+    source.await??;
+    Ok(pipeline)
+}
@@ -1,16 +1,57 @@
-use std::fs::File;
-use serde::Deserialize;
+use std::collections::HashSet;
+use serde::Serialize;
+use stop_names::{InterRegional, RegionMetaInformation};
 use super::{Error, HashMap, Junction};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Stop {
     pub name: String,
     pub lat: f64,
     pub lon: f64,
 }
 
-pub fn load(path: &str) -> Result<HashMap<Junction, Stop>, Box<dyn Error>> {
-    let file = File::open(path)?;
-    let stops = serde_json::from_reader(file)?;
-    Ok(stops)
+/// reporting points of the region with coordinates
+pub fn load(path: &str, region: u32) -> Result<HashMap<Junction, Stop>, Box<dyn Error>> {
+    let stops = InterRegional::from(path)
+        .ok_or_else(|| format!("cannot read stops from {}", path))?;
+    let reporting_points = stops.data.get(&region)
+        .ok_or_else(|| format!("{} contains no region {}", path, region))?;
+
+    Ok(reporting_points.keys()
+        .filter_map(|reporting_point| {
+            let position = stops.get_approximate_position(&region, reporting_point)?;
+            let (lat, lon) = position.coordinates()?;
+            Some((Junction(*reporting_point), Stop {
+                name: position.name.unwrap_or_default(),
+                lat,
+                lon,
+            }))
+        })
+        .collect())
+}
+
+/// reporting points of the region that stops.json lists without coordinates
+pub fn load_unlocated(path: &str, region: u32) -> Result<HashSet<Junction>, Box<dyn Error>> {
+    let stops = InterRegional::from(path)
+        .ok_or_else(|| format!("cannot read stops from {}", path))?;
+    let reporting_points = stops.data.get(&region)
+        .ok_or_else(|| format!("{} contains no region {}", path, region))?;
+
+    Ok(reporting_points.keys()
+        .filter(|reporting_point| stops.get_approximate_position(&region, reporting_point)
+            .and_then(|position| position.coordinates())
+            .is_none())
+        .map(|reporting_point| Junction(*reporting_point))
+        .collect())
+}
+
+/// meta information of a region, including the mapping between R09 line
+/// numbers and public line references
+pub fn load_meta(path: &str, region: u32) -> Result<RegionMetaInformation, Box<dyn Error>> {
+    let stops = InterRegional::from(path)
+        .ok_or_else(|| format!("cannot read stops from {}", path))?;
+
+    Ok(stops.meta.get(&region)
+        .cloned()
+        .unwrap_or_default())
 }
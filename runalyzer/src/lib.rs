@@ -0,0 +1,39 @@
+#![allow(clippy::type_complexity)]
+
+use std::collections::HashMap;
+use std::error::Error;
+use serde::{Deserialize, Serialize};
+use geo::{prelude::ClosestPoint, Closest};
+
+pub use stop_names::{Line, Run};
+
+pub mod telegram;
+pub mod cleaning;
+pub mod consistency;
+pub mod coverage;
+pub mod gaps;
+#[cfg(feature = "live")]
+pub mod ingest;
+pub mod osm_lines;
+mod osm_pbf;
+mod osm_xml;
+pub mod known_stops;
+pub mod segments;
+pub mod pipeline;
+pub mod prediction;
+pub mod map_matching;
+pub mod output;
+pub mod tracker;
+pub mod trips;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize)]
+pub struct LineRun {
+    pub line: Line,
+    pub run: Run,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
+pub struct Junction(pub u32);
@@ -1,200 +1,688 @@
+#![allow(clippy::type_complexity)]
+
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
-use serde::{Deserialize, Serialize};
-use geo::{prelude::ClosestPoint, Closest, Point};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use stop_names::{InterRegional, InterRegionalGraph, LineReferences, ReceiverRegistry, RegionGraph, RegionMetaInformation};
+#[cfg(feature = "live")]
+use runalyzer::ingest;
+use runalyzer::{cleaning, consistency, coverage, gaps, known_stops, map_matching, osm_lines, output, pipeline, segments, telegram, trips, Junction, Line, LineRun};
 
-mod telegram;
-mod osm_lines;
-mod known_stops;
-mod segments;
+/// Places the reporting points of telegram runs along the OSM line geometry.
+#[derive(Debug, Parser)]
+#[command(name = "runalyzer", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
-pub struct LineRun {
-    line: Line,
-    run: Run,
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Splits a telegram dump into line runs, one per trip
+    Runs {
+        #[command(flatten)]
+        telegrams: TelegramArgs,
+        #[command(flatten)]
+        stops: StopArgs,
+        #[command(flatten)]
+        filter: LineFilter,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Splits the line runs into trips and labels them with their OSM route
+    /// variant
+    Trips {
+        #[command(flatten)]
+        telegrams: TelegramArgs,
+        #[command(flatten)]
+        stops: StopArgs,
+        #[command(flatten)]
+        osm: OsmArgs,
+        #[command(flatten)]
+        filter: LineFilter,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Reports which reporting points each receiver hears, as coverage.json
+    /// and coverage.geojson
+    Coverage {
+        /// telegram dump in csv format, with an ip or station_id column
+        #[arg(long, default_value = "formatted.csv")]
+        telegrams: String,
+        /// registry of the receivers with their locations
+        #[arg(long, default_value = "receivers.json")]
+        receivers: String,
+        /// seconds in which telegrams of a vehicle at a junction are one passage
+        #[arg(long, default_value_t = coverage::PASSAGE_WINDOW.as_secs())]
+        passage_window: u64,
+        #[command(flatten)]
+        stops: StopArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Finds the known stops along the OSM lines
+    Match {
+        #[command(flatten)]
+        stops: StopArgs,
+        #[command(flatten)]
+        osm: OsmArgs,
+        #[command(flatten)]
+        filter: LineFilter,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Runs the whole pipeline and writes one <line>.json per line, plus the
+    /// positions of the new junctions aggregated over all lines
+    Segmentize {
+        #[command(flatten)]
+        telegrams: TelegramArgs,
+        #[command(flatten)]
+        stops: StopArgs,
+        #[command(flatten)]
+        osm: OsmArgs,
+        #[command(flatten)]
+        filter: LineFilter,
+        #[command(flatten)]
+        output: OutputArgs,
+        /// additionally write every line as <line>.geojson
+        #[arg(long)]
+        geojson: bool,
+    },
+    /// Checks graph.json against the telegram dump and stops.json, as
+    /// check.json
+    Check {
+        #[command(flatten)]
+        telegrams: TelegramArgs,
+        #[command(flatten)]
+        stops: StopArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Runs live telegrams through the outlier filter and the vehicle tracker
+    /// and prints them as json lines
+    #[cfg(feature = "live")]
+    Live {
+        /// udp address to receive json telegrams on
+        #[arg(long)]
+        udp: Option<std::net::SocketAddr>,
+        /// websocket url of the telegram collection server
+        #[arg(long)]
+        websocket: Option<String>,
+        /// csv dump to play back instead of a live source
+        #[arg(long)]
+        replay: Option<String>,
+        /// playback factor of --replay, 0 plays back without waiting
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+        /// graph.json used to check that junctions are reachable
+        #[arg(long)]
+        graph: Option<String>,
+        /// maximum plausible speed in m/s between two known stops
+        #[arg(long, default_value_t = 30.0)]
+        max_speed: f64,
+        #[command(flatten)]
+        stops: StopArgs,
+    },
+    /// Exports graph.json as graph.dot and graph.geojson for inspection
+    Graph {
+        /// graph.json to export
+        #[arg(long, default_value = "graph.json")]
+        graph: String,
+        #[command(flatten)]
+        stops: StopArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+    /// Proposes positions for the reporting points missing in stops.json
+    Locate {
+        #[command(flatten)]
+        telegrams: TelegramArgs,
+        #[command(flatten)]
+        stops: StopArgs,
+        #[command(flatten)]
+        osm: OsmArgs,
+        #[command(flatten)]
+        filter: LineFilter,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+}
+
+#[derive(Debug, Args)]
+struct TelegramArgs {
+    /// telegram dump in csv format
+    #[arg(long, default_value = "formatted.csv")]
+    telegrams: String,
+    /// seconds of silence after which a line run is considered finished
+    #[arg(long, default_value_t = telegram::RUN_MAX_GAP.as_secs())]
+    run_gap: u64,
+    /// graph.json used to check that junctions are reachable and to detect
+    /// turnarounds when splitting runs into trips
+    #[arg(long)]
+    graph: Option<String>,
+    /// seconds in which the same telegram from another receiver is a duplicate
+    #[arg(long, default_value_t = 10)]
+    dedup_window: u64,
+    /// seconds a telegram may arrive late and still be reordered
+    #[arg(long, default_value_t = 30)]
+    reorder_tolerance: u64,
+    /// maximum plausible speed in m/s between two known stops
+    #[arg(long, default_value_t = 30.0)]
+    max_speed: f64,
+    /// use the telegrams as they are
+    #[arg(long)]
+    no_cleaning: bool,
+    /// insert the junctions a run likely passed unheard, requires --graph
+    #[arg(long)]
+    fill_gaps: bool,
+    /// maximum number of junctions inferred between two reported ones
+    #[arg(long, default_value_t = gaps::MAX_SKIPPED)]
+    max_skipped: usize,
+}
+
+#[derive(Debug, Args)]
+struct StopArgs {
+    /// stops.json with the known reporting point positions and the line
+    /// references of the region
+    #[arg(long, default_value = "stops.json")]
+    stops: String,
+    /// region id inside of stops.json
+    #[arg(long, default_value_t = 0)]
+    region: u32,
+}
+
+#[derive(Debug, Args)]
+struct OsmArgs {
+    /// overpass json export, .osm or .osm.pbf extract with the route
+    /// relations, can be given multiple times
+    #[arg(long = "osm", default_values = ["trams.json"])]
+    osm: Vec<String>,
+    /// only use this relation and its member relations, can be given multiple times
+    #[arg(long = "relation", allow_negative_numbers = true)]
+    relations: Vec<i64>,
+    /// only use relations with this network tag, can be given multiple times
+    #[arg(long = "network")]
+    networks: Vec<String>,
+    /// only use relations with this operator tag, can be given multiple times
+    #[arg(long = "operator")]
+    operators: Vec<String>,
+    /// maximum distance in meters between a known stop and the OSM way
+    #[arg(long, default_value_t = segments::MAX_WAY_DISTANCE)]
+    max_distance: f64,
+}
+
+#[derive(Debug, Args)]
+struct LineFilter {
+    /// only process these lines, by R09 line number or public reference,
+    /// can be given multiple times
+    #[arg(long = "line")]
+    lines: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct OutputArgs {
+    /// directory the result files are written into
+    #[arg(long, default_value = ".")]
+    output_dir: PathBuf,
+    /// serialization of the result files
+    #[arg(long, value_enum, default_value_t = Format::PrettyJson)]
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    PrettyJson,
+}
+
+impl LineFilter {
+    fn contains(&self, line: &Line) -> bool {
+        self.lines.is_empty() || self.lines.iter()
+            .any(|filter| *filter == line.reference() || *filter == line.number.to_string())
+    }
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize)]
-pub struct Line(u16);
+impl OutputArgs {
+    fn write<T: Serialize>(&self, filename: &str, data: &T) -> Result<(), Box<dyn Error>> {
+        let path = self.output_dir.join(filename);
+        println!("Writing {}", path.display());
+        let f = File::create(path)?;
+        match self.format {
+            Format::Json => serde_json::to_writer(f, data)?,
+            Format::PrettyJson => serde_json::to_writer_pretty(f, data)?,
+        }
+        Ok(())
+    }
+
+    fn write_text(&self, filename: &str, text: &str) -> Result<(), Box<dyn Error>> {
+        let path = self.output_dir.join(filename);
+        println!("Writing {}", path.display());
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize)]
-pub struct Run(u16);
+#[derive(Debug, Serialize)]
+struct RunResult {
+    #[serde(flatten)]
+    line_run: LineRun,
+    /// unix timestamp and junction
+    junctions: Vec<(u64, Junction)>,
+}
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize)]
-pub struct Junction(u32);
+#[derive(Debug, Serialize)]
+struct TripResult {
+    #[serde(flatten)]
+    trip: trips::Trip,
+    /// unix timestamp and junction
+    junctions: Vec<(u64, Junction)>,
+}
 
 #[derive(Debug, Serialize)]
-pub struct ResultSegments {
-    line: String,
-    segments: Vec<segments::ResultSegment>,
+struct MatchResult {
+    line: Line,
+    name: String,
+    /// junction and position in the order of the OSM ways
+    known_stops: Vec<(Junction, [f64; 2])>,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    match Cli::parse().command {
+        Command::Runs { telegrams, stops, filter, output } => {
+            let region = load_region(&stops)?;
+            let runs = load_runs(&telegrams, &region, &filter)?
+                .into_iter()
+                .map(|(line_run, junctions)| RunResult {
+                    line_run,
+                    junctions: unix_junctions(&junctions),
+                })
+                .collect::<Vec<_>>();
+            output.write("runs.json", &runs)
+        }
+        Command::Trips { telegrams, stops, osm, filter, output } => {
+            let region = load_region(&stops)?;
+            let mut trips = load_trips(&telegrams, &region, &filter)?;
+            let line_infos = load_lines(&osm, &region.line_references, &filter)?
+                .into_values()
+                .flatten()
+                .collect::<Vec<_>>();
+            trips::label_trips(&mut trips, &line_infos, &region.stops, osm.max_distance);
+            println!("{} of {} trips follow an OSM route variant", trips.iter().filter(|trip| trip.variant.is_some()).count(), trips.len());
+
+            let trips = trips.into_iter()
+                .map(|trip| TripResult {
+                    junctions: unix_junctions(&trip.junctions()),
+                    trip,
+                })
+                .collect::<Vec<_>>();
+            output.write("trips.json", &trips)
+        }
+        Command::Coverage { telegrams, receivers, passage_window, stops, output } => {
+            let region = load_region(&stops)?;
+            let registry = ReceiverRegistry::from(&receivers)
+                .ok_or_else(|| format!("cannot read receivers from {}", receivers))?;
+            let telegrams = read_dump(&telegrams)?;
+            let coverage = coverage::coverage(&telegrams, &registry, Duration::from_secs(passage_window));
+            for receiver in &coverage {
+                println!("{} hears {} junctions", receiver.receiver, receiver.junctions.len());
+            }
+            output.write("coverage.json", &coverage)?;
+            output.write("coverage.geojson", &coverage::heatmap(&coverage, &registry, &region.stops))
+        }
+        Command::Match { stops, osm, filter, output } => {
+            let region = load_region(&stops)?;
+            let mut results = vec![];
+            for (line, line_infos) in load_lines(&osm, &region.line_references, &filter)? {
+                for line_info in line_infos {
+                    let known_stops = pipeline::find_known_stops(&region.stops, &line_info, osm.max_distance);
+                    println!("Found {} known stops in OSM {}", known_stops.len(), line_info.name);
+                    results.push(MatchResult {
+                        line: line.clone(),
+                        name: line_info.name,
+                        known_stops: known_stops.into_iter()
+                            .map(|(_, junction, point)| (junction, [point.x(), point.y()]))
+                            .collect(),
+                    });
+                }
+            }
+            output.write("matches.json", &results)
+        }
+        Command::Segmentize { telegrams, stops, osm, filter, output, geojson } => {
+            segmentize(&telegrams, &stops, &osm, &filter, &output, geojson)
+        }
+        Command::Check { telegrams, stops, output } => {
+            let region = load_region(&stops)?;
+            let graph = load_graph(&telegrams, region.id)?
+                .ok_or("check requires --graph")?;
+            let mut dump = read_dump(&telegrams.telegrams)?;
+            if !telegrams.no_cleaning {
+                // without the graph, so that transitions missing in it are kept
+                let config = cleaning::CleaningConfig {
+                    dedup_window: Duration::from_secs(telegrams.dedup_window),
+                    reorder_tolerance: Duration::from_secs(telegrams.reorder_tolerance),
+                    max_speed: telegrams.max_speed,
+                    ..Default::default()
+                };
+                let (cleaned, rejections) = cleaning::clean(dump, &config, None, &region.stops);
+                println!("dropped {} telegrams: {}", rejections.total(), rejections);
+                dump = cleaned;
+            }
+            let runs = telegram::group_runs(&dump, Duration::from_secs(telegrams.run_gap), &region.line_references);
+            println!("{} telegrams form {} line runs", dump.len(), runs.len());
+            let report = consistency::check(&graph, &runs, &region.stops, &region.unlocated, telegrams.max_speed, cleaning::CleaningConfig::default().max_gap);
+            println!("{}", report);
+            output.write("check.json", &report)
+        }
+        #[cfg(feature = "live")]
+        Command::Live { udp, websocket, replay, speed, graph, max_speed, stops } => {
+            let source = match (udp, websocket, replay) {
+                (Some(address), None, None) => ingest::Source::Udp(address),
+                (None, Some(url), None) => ingest::Source::WebSocket(url),
+                (None, None, Some(path)) => ingest::Source::Replay { path, speed },
+                _ => return Err("exactly one of --udp, --websocket and --replay is required".into()),
+            };
+            let region = load_region(&stops)?;
+            let graph = graph.map(|path| read_graph(&path, region.id)).transpose()?;
+            let config = cleaning::CleaningConfig {
+                max_speed,
+                ..Default::default()
+            };
+            live(source, ingest::Pipeline::new(region.stops, graph, config))
+        }
+        Command::Graph { graph, stops, output } => {
+            let region_graph = read_graph(&graph, stops.region)?;
+            let positions = InterRegional::from(&stops.stops)
+                .ok_or_else(|| format!("cannot read stops from {}", stops.stops))?
+                .data
+                .remove(&stops.region)
+                .unwrap_or_default();
+            output.write_text("graph.dot", &region_graph.to_dot(&positions))?;
+            let geojson = region_graph.to_geojson(&positions);
+            let unlocated = geojson["features"].as_array()
+                .map_or(0, |features| features.iter().filter(|feature| feature["geometry"].is_null()).count());
+            println!("{} edges lack coordinates", unlocated);
+            output.write("graph.geojson", &geojson)
+        }
+        Command::Locate { telegrams, stops, osm, filter, output } => {
+            locate(&telegrams, &stops, &osm, &filter, &output)
+        }
+    }
+}
+
+/// everything stops.json knows about the region
+struct Region {
+    id: u32,
+    stops: HashMap<Junction, known_stops::Stop>,
+    /// reporting points without coordinates
+    unlocated: HashSet<Junction>,
+    line_references: LineReferences,
+    meta: RegionMetaInformation,
+}
+
+fn load_region(args: &StopArgs) -> Result<Region, Box<dyn Error>> {
     println!("loading known stops");
-    let stops = known_stops::load("../stops.json")?;
-    let known_stops = stops.keys().copied().collect::<HashSet<_>>();
+    let stops = known_stops::load(&args.stops, args.region)?;
     println!("{} stops loaded", stops.len());
+    let unlocated = known_stops::load_unlocated(&args.stops, args.region)?;
+    let meta = known_stops::load_meta(&args.stops, args.region)?;
+    let line_references = meta.lines.clone();
+    println!("{} line references loaded", line_references.len());
+    Ok(Region {
+        id: args.region,
+        stops,
+        unlocated,
+        line_references,
+        meta,
+    })
+}
 
+fn unix_junctions(junctions: &[(SystemTime, Junction)]) -> Vec<(u64, Junction)> {
+    junctions.iter()
+        .map(|(time, junction)| (
+            time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs(),
+            *junction,
+        ))
+        .collect()
+}
+
+#[cfg(feature = "live")]
+fn live(source: ingest::Source, pipeline: ingest::Pipeline) -> Result<(), Box<dyn Error>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let (sink, mut receiver) = tokio::sync::mpsc::channel(ingest::CHANNEL_CAPACITY);
+        let ingest = tokio::spawn(ingest::ingest(source, pipeline, sink));
+        while let Some(live) = receiver.recv().await {
+            println!("{}", serde_json::to_string(&live)?);
+        }
+        let pipeline = ingest.await?.map_err(|e| e as Box<dyn Error>)?;
+        eprintln!("skipped {} messages that are no telegrams", pipeline.unparsable);
+        eprintln!("dropped {} telegrams: {}", pipeline.rejections.total(), pipeline.rejections);
+        eprintln!("tracked {} vehicles", pipeline.tracker.vehicles().count());
+        Ok(())
+    })
+}
+
+fn read_dump(path: &str) -> Result<Vec<telegram::Telegram>, Box<dyn Error>> {
+    let (telegrams, errors) = telegram::read_csv(path)?;
+    for error in &errors {
+        eprintln!("Parse error: {}", error);
+    }
+    println!("{}: parsed {} telegrams, {} errors", path, telegrams.len(), errors.len());
+    Ok(telegrams)
+}
+
+fn load_graph(args: &TelegramArgs, region: u32) -> Result<Option<RegionGraph>, Box<dyn Error>> {
+    args.graph.as_deref()
+        .map(|path| read_graph(path, region))
+        .transpose()
+}
+
+fn read_graph(path: &str, region: u32) -> Result<RegionGraph, Box<dyn Error>> {
+    Ok(InterRegionalGraph::from(path)
+        .ok_or_else(|| format!("cannot read graph from {}", path))?
+        .extract(&region)
+        .ok_or_else(|| format!("{} contains no region {}", path, region))?)
+}
+
+/// cleaned runs split into trips
+fn load_trips(
+    args: &TelegramArgs,
+    region: &Region,
+    filter: &LineFilter,
+) -> Result<Vec<trips::Trip>, Box<dyn Error>> {
     println!("reading telegrams");
-    let run_junctions = telegram::read_telegrams("../formatted.csv")?;
+    let telegrams = read_dump(&args.telegrams)?;
+    let graph = load_graph(args, region.id)?;
+    let telegrams = if args.no_cleaning {
+        telegrams
+    } else {
+        let config = cleaning::CleaningConfig {
+            dedup_window: Duration::from_secs(args.dedup_window),
+            reorder_tolerance: Duration::from_secs(args.reorder_tolerance),
+            max_speed: args.max_speed,
+            ..Default::default()
+        };
+        let (telegrams, rejections) = cleaning::clean(telegrams, &config, graph.as_ref(), &region.stops);
+        println!("dropped {} telegrams: {}", rejections.total(), rejections);
+        telegrams
+    };
+
+    let mut runs = telegram::group_runs(&telegrams, Duration::from_secs(args.run_gap), &region.line_references);
+    println!("{} telegrams form {} line runs", telegrams.len(), runs.len());
+    runs.retain(|(line_run, _)| filter.contains(&line_run.line));
+    let trips = trips::split_runs(&runs, graph.as_ref());
+    println!("split {} line runs into {} trips", runs.len(), trips.len());
+    Ok(trips)
+}
+
+fn load_runs(
+    args: &TelegramArgs,
+    region: &Region,
+    filter: &LineFilter,
+) -> Result<Vec<(LineRun, Vec<(SystemTime, Junction)>)>, Box<dyn Error>> {
+    let graph = if args.fill_gaps {
+        Some(load_graph(args, region.id)?.ok_or("--fill-gaps requires --graph")?)
+    } else {
+        None
+    };
+    let runs = load_trips(args, region, filter)?
+        .into_iter()
+        .map(|trip| {
+            let junctions = trip.junctions();
+            (trip.line_run, junctions)
+        })
+        .collect::<Vec<_>>();
+    let Some(graph) = graph else {
+        return Ok(runs);
+    };
+
+    let mut inferred = 0;
+    let runs = runs.into_iter()
+        .map(|(line_run, junctions)| {
+            let passages = gaps::fill_gaps(&junctions, &graph, Some(line_run.line.number), args.max_skipped);
+            inferred += passages.len() - junctions.len();
+            (line_run, gaps::timeline(&passages))
+        })
+        .collect();
+    println!("inferred {} skipped junctions", inferred);
+    Ok(runs)
+}
+
+fn load_lines(
+    args: &OsmArgs,
+    line_references: &LineReferences,
+    filter: &LineFilter,
+) -> Result<HashMap<Line, Vec<osm_lines::LineInfo>>, Box<dyn Error>> {
+    let relation_filter = osm_lines::RelationFilter {
+        relations: args.relations.clone(),
+        networks: args.networks.clone(),
+        operators: args.operators.clone(),
+    };
+    let mut lines = HashMap::<Line, Vec<osm_lines::LineInfo>>::new();
+    for path in &args.osm {
+        println!("reading osm export {}", path);
+        let (line_infos, reports) = osm_lines::read_with_reports(path, &relation_filter)?;
+        for report in reports {
+            eprintln!("{}", report);
+        }
+        for mut line_info in line_infos {
+            let Some(line) = Line::from_reference(&line_info.reference, line_references) else {
+                println!("{}: no R09 line number for ref {}", line_info.name, line_info.reference);
+                continue;
+            };
+            line_info.line = Some(line.clone());
+            if filter.contains(&line) {
+                lines.entry(line)
+                    .or_default()
+                    .push(line_info);
+            }
+        }
+    }
+    Ok(lines)
+}
+
+fn segmentize(
+    telegram_args: &TelegramArgs,
+    stop_args: &StopArgs,
+    osm_args: &OsmArgs,
+    filter: &LineFilter,
+    output: &OutputArgs,
+    geojson: bool,
+) -> Result<(), Box<dyn Error>> {
+    let region = load_region(stop_args)?;
+    let stops = &region.stops;
+    let known_stops = stops.keys().copied().collect::<HashSet<_>>();
+
+    let run_junctions = load_runs(telegram_args, &region, filter)?;
     let junctions_by_known_stops = segments::junctions_by_known_stops(
         &known_stops,
         run_junctions
     );
 
-    let mut lines = HashMap::<Line, Vec<osm_lines::LineInfo>>::new();
-    for line_info in osm_lines::read("../trams.json")?.into_iter()
-        .chain(osm_lines::read("../buses.json")?.into_iter())
-    {
-        lines.entry(line_info.line)
-            .or_default()
-            .push(line_info);
-    }
+    let mut samples = HashMap::<Junction, Vec<map_matching::Sample>>::new();
+    for (line, line_infos) in load_lines(osm_args, &region.line_references, filter)? {
+        let variants = line_infos.into_iter()
+            .filter_map(|line_info| {
+                let (variant, report) = pipeline::analyze_line(
+                    line_info,
+                    stops,
+                    &junctions_by_known_stops,
+                    osm_args.max_distance,
+                );
+                println!("{}", report);
+                variant
+            })
+            .collect::<Vec<_>>();
 
-    for (line, line_infos) in lines {
-        let mut line_results = vec![];
-
-        for line_info in line_infos {
-            let mut line_known_stops = stops.iter().filter_map(|(junction, stop)| {
-                let known_point = Point::new(stop.lon, stop.lat);
-                segments::way_point(&line_info.ways, &known_point)
-                    .map(|(index, point)| (index, *junction, point))
-            }).collect::<Vec<_>>();
-            line_known_stops.sort_by_key(|(index, _, _)| *index);
-            println!("Found {} known stops in OSM {}", line_known_stops.len(), line_info.name);
-            if line_known_stops.len() < 2 {
-                continue;
+        for variant in &variants {
+            for (junction, junction_samples) in map_matching::variant_samples(variant, &known_stops) {
+                samples.entry(junction)
+                    .or_default()
+                    .extend(junction_samples);
             }
+        }
+        let line_document = output::LineDocument::new(line, variants);
+        output.write(&format!("{}.json", line_document.line), &line_document)?;
+        if geojson {
+            output.write(&format!("{}.geojson", line_document.line), &line_document.geojson())?;
+        }
+    }
 
-            let known_stop_junctions = line_known_stops.iter()
-                .map(|(_, junction, _)| junction)
-                .copied()
-                .collect::<Vec<Junction>>();
-            let mut best_length = 0;
-            let mut matching_runs = vec![];
-            for (line_run, known_junctions, junctions) in &junctions_by_known_stops {
-                if line_run.line != line {
-                    continue;
-                }
+    write_estimates(output, &region, &samples)
+}
 
-                if known_junctions.len() > 1
-                && is_similar_sequence(known_junctions, &known_stop_junctions) {
+/// writes the estimates with their errors and the patch for stops.json
+fn write_estimates(
+    output: &OutputArgs,
+    region: &Region,
+    samples: &HashMap<Junction, Vec<map_matching::Sample>>,
+) -> Result<(), Box<dyn Error>> {
+    let estimates = map_matching::estimate_positions(samples);
+    println!("Estimated {} reporting point positions", estimates.len());
+    output.write("positions.json", &estimates)?;
+    output.write("stops-patch.json", &map_matching::proposed_patch(region.id, &region.meta, &estimates))
+}
 
-                    if known_stop_junctions.len() > best_length {
-                        best_length = known_stop_junctions.len();
-                        matching_runs = vec![];
-                    }
+fn locate(
+    telegram_args: &TelegramArgs,
+    stop_args: &StopArgs,
+    osm_args: &OsmArgs,
+    filter: &LineFilter,
+    output: &OutputArgs,
+) -> Result<(), Box<dyn Error>> {
+    let region = load_region(stop_args)?;
+    let stops = &region.stops;
+    let known_stops = stops.keys().copied().collect::<HashSet<_>>();
 
-                    matching_runs.push(junctions);
-                }
-            }
-            println!("telegrams contain {} good matching runs", matching_runs.len());
-            // longest known junctions segment between known stations
-            let mut longest_segments = HashMap::new();
-            let mut min_durations = HashMap::new();
-            for junctions in matching_runs {
-                // best junction path between stations
-                for ((start, stop), segment) in segments::segment_run_by_known_stops(&known_stops, junctions) {
-                    let mut last_junction = None;
-                    for (duration, junction) in segment.clone() {
-                        if let Some(last_junction) = last_junction.take() {
-                            let min_duration = min_durations.entry((last_junction, junction))
-                                .or_insert(duration);
-                            if duration < *min_duration {
-                                *min_duration = duration;
-                            }
-                        }
-
-                        last_junction = Some(junction);
-                    }
-
-                    let longest_segment = longest_segments.entry((start, stop))
-                        .or_insert_with(|| segment.clone());
-                    if longest_segment.len() < segment.len() {
-                        *longest_segment = segment.clone();
-                    }
-                }
+    let run_junctions = load_runs(telegram_args, &region, filter)?;
+    let junctions_by_known_stops = segments::junctions_by_known_stops(
+        &known_stops,
+        run_junctions
+    );
 
+    let mut samples = HashMap::<Junction, Vec<map_matching::Sample>>::new();
+    for line_infos in load_lines(osm_args, &region.line_references, filter)?.into_values() {
+        for line_info in line_infos {
+            let line_samples = map_matching::match_line(
+                &line_info,
+                stops,
+                &junctions_by_known_stops,
+                osm_args.max_distance,
+            );
+            println!("Placed {} junctions along OSM {}", line_samples.len(), line_info.name);
+            for (junction, junction_samples) in line_samples {
+                samples.entry(junction)
+                    .or_default()
+                    .extend(junction_samples);
             }
-            // apply min_durations with each longest_segment
-            let longest_segments = longest_segments.into_iter()
-                .map(|((start, stop), segment)| {
-                    let mut last_junction = None;
-                    let mut min_segment = Vec::with_capacity(segment.len());
-                    for (duration, junction) in segment {
-                        let result = if let Some(last_junction) = last_junction {
-                            if let Some(min_duration) = min_durations.get(&(last_junction, junction)) {
-                                (*min_duration, junction)
-                            } else {
-                                println!("No best duration for {:?}", (last_junction, junction));
-                                (duration, junction)
-                            }
-                        } else {
-                                (duration, junction)
-                        };
-                        min_segment.push(result);
-
-                        last_junction = Some(junction);
-                    }
-                    ((start, stop), min_segment)
-                })
-                .collect::<HashMap<_, _>>();
-
-            let mut last = None;
-            let known_stop_segments = line_known_stops.into_iter()
-                .filter_map(|(_, junction, point)| {
-                    let result = if let Some((last_junction, last_point)) = last.take() {
-                        if let Some(longest_segment) = longest_segments.get(&(last_junction, junction)) {
-                            let junctions = segments::to_rational(longest_segment);
-                            Some(segments::Segment {
-                                start: (last_junction, last_point),
-                                stop: (junction, point),
-                                junctions,
-                            })
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    };
-
-                    last = Some((junction, point));
-                    result
-                }).collect::<Vec<_>>();
-            let new_junctions: usize = known_stop_segments.iter().map(|segment|
-                segment.junctions.iter().filter(|(_, junction)|
-                    *junction != segment.start.0 &&
-                    *junction != segment.stop.0
-                ).count()
-            ).sum();
-            println!("Adding {} new junctions to {:?} ways", new_junctions, line_info.ways.iter().map(std::vec::Vec::len).collect::<Vec<_>>());
-            let segment_results = known_stop_segments.into_iter()
-                .flat_map(|segment| segments::segmentize(&segment, &line_info.ways))
-                .flatten()
-                .collect::<Vec<_>>();
-            line_results.push(ResultSegments {
-                line: line_info.name,
-                segments: segment_results,
-            });
-        }
-
-        let filename = format!("{}.json", line.0);
-        println!("Writing {}", filename);
-        let f = File::create(filename)
-            .unwrap();
-        serde_json::to_writer_pretty(f, &line_results)
-            .unwrap();
+        }
     }
 
-    Ok(())
-}
-
-fn is_similar_sequence(partial: &[Junction], goal: &[Junction]) -> bool {
-    let partial_set = partial.iter().collect::<HashSet<_>>();
-    let partial_of_goal = goal.iter()
-        .filter(|g| partial_set.contains(g))
-        .copied()
-        .collect::<Vec<_>>();
-    &partial_of_goal[..] == partial
+    write_estimates(output, &region, &samples)
 }
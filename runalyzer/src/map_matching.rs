@@ -0,0 +1,237 @@
+//! Estimates the positions of reporting points without coordinates. The
+//! travel time ratios between two known stops are mapped onto the OSM track
+//! between them, for every run that passes both.
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+use geo::{prelude::{GeodesicDistance, HaversineDistance}, Line, Point};
+use serde::Serialize;
+use stop_names::{InterRegional, PositionStatus, RegionMetaInformation, TelegramType, TransmissionPosition};
+use super::known_stops::Stop;
+use super::osm_lines::{LineInfo, Waypoint};
+use super::output::{self, RouteVariant};
+use super::{pipeline, segments, Closest, ClosestPoint, Junction, LineRun};
+
+/// schema version of stops.json
+pub const STOPS_SCHEMA_VERSION: &str = "1.0";
+
+/// lower bound in meters for the error of an estimate
+pub const MIN_ERROR: f64 = 10.0;
+
+/// One way of a route variant with the along-track distance of every point
+/// in meters.
+#[derive(Debug, Clone)]
+pub struct Track {
+    points: Vec<Point<f64>>,
+    distances: Vec<f64>,
+}
+
+impl Track {
+    pub fn new(way: &[Waypoint]) -> Track {
+        let points = way.iter()
+            .map(|waypoint| Point::new(waypoint.lon, waypoint.lat))
+            .collect::<Vec<_>>();
+        let mut distance = 0.0;
+        let distances = points.iter()
+            .enumerate()
+            .map(|(index, point)| {
+                if index > 0 {
+                    distance += points[index - 1].geodesic_distance(point);
+                }
+                distance
+            })
+            .collect();
+        Track { points, distances }
+    }
+
+    pub fn length(&self) -> f64 {
+        self.distances.last().copied().unwrap_or_default()
+    }
+
+    /// along-track distance of the closest point on the track and the
+    /// distance of `point` to it
+    pub fn project(&self, point: &Point<f64>) -> Option<(f64, f64)> {
+        self.points.windows(2)
+            .zip(&self.distances)
+            .filter_map(|(pair, distance)| {
+                match Line::new(pair[0], pair[1]).closest_point(point) {
+                    Closest::Intersection(p) | Closest::SinglePoint(p) => Some((
+                        distance + pair[0].geodesic_distance(&p),
+                        point.geodesic_distance(&p),
+                    )),
+                    Closest::Indeterminate => None,
+                }
+            })
+            .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2))
+    }
+
+    /// point at the along-track distance `along`
+    pub fn interpolate(&self, along: f64) -> Option<Point<f64>> {
+        let index = self.distances.windows(2)
+            .position(|pair| along <= pair[1])?;
+        let (start, stop) = (self.distances[index], self.distances[index + 1]);
+        let ratio = if stop > start { (along - start) / (stop - start) } else { 0.0 };
+        let (p1, p2) = (self.points[index], self.points[index + 1]);
+        Some(p1 + (p2 - p1) * ratio.max(0.0))
+    }
+}
+
+/// A single placement of a junction.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub point: Point<f64>,
+    /// track length in meters between the known stops around the junction
+    pub segment_length: f64,
+}
+
+/// Proposed position of a reporting point.
+#[derive(Debug, Clone, Serialize)]
+pub struct PositionEstimate {
+    pub junction: Junction,
+    pub position: TransmissionPosition,
+    /// estimated error radius in meters
+    pub error: f64,
+    pub samples: usize,
+}
+
+/// Places the unknown junctions of all runs matching a route variant on its
+/// tracks.
+pub fn match_line(
+    line_info: &LineInfo,
+    stops: &HashMap<Junction, Stop>,
+    junctions_by_known_stops: &[(LineRun, Vec<Junction>, Vec<(SystemTime, Junction)>)],
+    max_distance: f64,
+) -> HashMap<Junction, Vec<Sample>> {
+    let mut samples = HashMap::<Junction, Vec<Sample>>::new();
+    let Some(line) = line_info.line.clone() else {
+        return samples;
+    };
+    let tracks = line_info.ways.iter()
+        .map(|way| Track::new(way))
+        .collect::<Vec<_>>();
+
+    // track index and along-track distance of the known stops
+    let mut known = stops.iter()
+        .filter_map(|(junction, stop)| {
+            let point = Point::new(stop.lon, stop.lat);
+            tracks.iter()
+                .enumerate()
+                .filter_map(|(index, track)| track.project(&point)
+                    .map(|(along, offset)| (index, along, offset)))
+                .filter(|(_, _, offset)| *offset < max_distance)
+                .min_by(|(_, _, d1), (_, _, d2)| d1.total_cmp(d2))
+                .map(|(index, along, _)| (*junction, (index, along)))
+        })
+        .collect::<Vec<_>>();
+    known.sort_by(|(_, (i1, a1)), (_, (i2, a2))| i1.cmp(i2).then(a1.total_cmp(a2)));
+    let known_stop_junctions = known.iter()
+        .map(|(junction, _)| *junction)
+        .collect::<Vec<_>>();
+    let known = known.into_iter().collect::<HashMap<_, _>>();
+    let known_stops = stops.keys().copied().collect::<HashSet<_>>();
+
+    for run in pipeline::matching_runs(line, &known_stop_junctions, junctions_by_known_stops) {
+        for ((start, stop), durations) in segments::segment_run_by_known_stops(&known_stops, run) {
+            let (Some((start_track, start_along)), Some((stop_track, stop_along))) = (known.get(&start), known.get(&stop)) else {
+                continue;
+            };
+            if start_track != stop_track || start_along >= stop_along {
+                continue;
+            }
+            let segment_length = stop_along - start_along;
+            for (ratio, junction) in segments::to_rational(&durations) {
+                if known_stops.contains(&junction) {
+                    continue;
+                }
+                if let Some(point) = tracks[*start_track].interpolate(start_along + ratio * segment_length) {
+                    samples.entry(junction)
+                        .or_default()
+                        .push(Sample { point, segment_length });
+                }
+            }
+        }
+    }
+
+    samples
+}
+
+/// Placements of the unknown junctions of a route variant analyzed by
+/// `pipeline::analyze_line`.
+pub fn variant_samples(
+    variant: &RouteVariant,
+    known_stops: &HashSet<Junction>,
+) -> HashMap<Junction, Vec<Sample>> {
+    let mut samples = HashMap::<Junction, Vec<Sample>>::new();
+    for segment in &variant.segments {
+        for placed in &segment.junctions {
+            if known_stops.contains(&placed.junction) {
+                continue;
+            }
+            samples.entry(placed.junction)
+                .or_default()
+                .push(Sample {
+                    point: Point::new(placed.lon, placed.lat),
+                    segment_length: segment.length,
+                });
+        }
+    }
+    samples
+}
+
+/// Combines the samples of a junction into the sample with the smallest
+/// distance to all others. The error is the root mean square distance of the
+/// samples, or half of the segment for a single sample.
+pub fn estimate(junction: Junction, samples: &[Sample]) -> Option<PositionEstimate> {
+    let distances = samples.iter()
+        .map(|sample| samples.iter()
+            .map(|other| sample.point.haversine_distance(&other.point).powi(2))
+            .sum::<f64>())
+        .collect::<Vec<_>>();
+    let (medoid, squared_sum) = distances.iter()
+        .enumerate()
+        .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2))?;
+    let point = samples[medoid].point;
+
+    let error = if samples.len() == 1 {
+        samples[0].segment_length / 2.0
+    } else {
+        (squared_sum / samples.len() as f64).sqrt()
+    };
+
+    Some(PositionEstimate {
+        junction,
+        position: TransmissionPosition {
+            dhid: None,
+            name: None,
+            // like all reporting points of stops.json
+            telegram_type: TelegramType::DoorClosed,
+            direction: 0,
+            lat: Some(point.y()),
+            lon: Some(point.x()),
+            status: PositionStatus::EstimatedFromOsm,
+            accuracy: Some(error.max(MIN_ERROR)),
+        },
+        error: error.max(MIN_ERROR),
+        samples: samples.len(),
+    })
+}
+
+/// Estimates for all junctions, ordered by junction.
+pub fn estimate_positions(samples: &HashMap<Junction, Vec<Sample>>) -> Vec<PositionEstimate> {
+    let mut estimates = samples.iter()
+        .filter_map(|(junction, samples)| estimate(*junction, samples))
+        .collect::<Vec<_>>();
+    estimates.sort_by_key(|estimate| estimate.junction);
+    estimates
+}
+
+/// Proposed additions to stops.json for review, with the error estimates
+/// as accuracy and the meta information of the region they belong to.
+pub fn proposed_patch(region: u32, meta: &RegionMetaInformation, estimates: &[PositionEstimate]) -> InterRegional {
+    InterRegional {
+        document: output::document(STOPS_SCHEMA_VERSION),
+        data: stop_names::HashMap::from([(region, estimates.iter()
+            .map(|estimate| (estimate.junction.0, vec![estimate.position.clone()]))
+            .collect())]),
+        meta: stop_names::HashMap::from([(region, meta.clone())]),
+    }
+}
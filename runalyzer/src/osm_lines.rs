@@ -1,19 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs::File;
-use serde::Deserialize;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
 use geo::{prelude::GeodesicDistance, Point};
-use super::{Error, Line};
+use super::{osm_pbf, osm_xml, Error, Line};
 
 #[derive(Debug, Deserialize)]
 struct OverpassJson {
     elements: Vec<Record>,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize)]
-pub struct Id(u64);
+/// OSM element id, negative for objects that were never uploaded, like in
+/// files saved by JOSM
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
+pub struct Id(pub i64);
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize)]
-enum RecordType {
+pub enum RecordType {
     #[serde(rename = "node")]
     Node,
     #[serde(rename = "way")]
@@ -23,18 +27,18 @@ enum RecordType {
 }
 
 #[derive(Debug, Clone, Deserialize)]
-struct Record {
+pub(crate) struct Record {
     #[serde(rename = "type")]
-    record_type: RecordType,
-    id: Id,
+    pub(crate) record_type: RecordType,
+    pub(crate) id: Id,
     // for nodes
-    lat: Option<f64>,
-    lon: Option<f64>,
+    pub(crate) lat: Option<f64>,
+    pub(crate) lon: Option<f64>,
     // for ways
-    nodes: Option<Vec<Id>>,
+    pub(crate) nodes: Option<Vec<Id>>,
     // for relations
-    members: Option<Vec<RelationMember>>,
-    tags: Option<HashMap<String, String>>,
+    pub(crate) members: Option<Vec<RelationMember>>,
+    pub(crate) tags: Option<HashMap<String, String>>,
 }
 
 impl Record {
@@ -49,32 +53,155 @@ impl Record {
         })
     }
 
-    fn line_stop(&self) -> Option<LineStop> {
-        if self.record_type != RecordType::Node {
-            println!("stop is not a node: {:?}", self);
-        }
-        Some(LineStop {
-            name: self.tags.as_ref()?.get("name")?.to_string(),
-            lat: self.lat?,
-            lon: self.lon?,
-        })
+    fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.as_ref()?
+            .get(key)
+            .map(std::string::String::as_str)
     }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize)]
-struct RelationMember {
+pub(crate) struct RelationMember {
     #[serde(rename = "type")]
-    record_type: RecordType,
+    pub(crate) record_type: RecordType,
     #[serde(rename = "ref")]
-    record_ref: Id,
-    role: String,
+    pub(crate) record_ref: Id,
+    pub(crate) role: String,
+}
+
+/// Selects route relations by id, `network` or `operator` tag. Members of
+/// selected relations, like the routes of a route_master, are selected,
+/// too. An empty filter selects every relation.
+#[derive(Debug, Clone, Default)]
+pub struct RelationFilter {
+    pub relations: Vec<i64>,
+    pub networks: Vec<String>,
+    pub operators: Vec<String>,
+}
+
+impl RelationFilter {
+    pub fn is_empty(&self) -> bool {
+        self.relations.is_empty() && self.networks.is_empty() && self.operators.is_empty()
+    }
+
+    fn matches(&self, record: &Record) -> bool {
+        let tag = |key: &str| record.tags.as_ref()
+            .and_then(|tags| tags.get(key));
+        self.relations.contains(&record.id.0)
+            || tag("network").is_some_and(|network| self.networks.contains(network))
+            || tag("operator").is_some_and(|operator| self.operators.contains(operator))
+    }
+
+    fn selected_relations<'a>(&self, relations: impl Iterator<Item = &'a Record>) -> HashSet<Id> {
+        let relations = relations
+            .filter(|record| record.record_type == RecordType::Relation)
+            .map(|record| (record.id, record))
+            .collect::<HashMap<_, _>>();
+        if self.is_empty() {
+            return relations.keys().copied().collect();
+        }
+
+        let mut selected = HashSet::new();
+        let mut queue = relations.values()
+            .filter(|record| self.matches(record))
+            .map(|record| record.id)
+            .collect::<Vec<_>>();
+        while let Some(id) = queue.pop() {
+            if !selected.insert(id) {
+                continue;
+            }
+            let members = relations.get(&id)
+                .and_then(|record| record.members.as_ref());
+            for member in members.into_iter().flatten() {
+                if member.record_type == RecordType::Relation {
+                    queue.push(member.record_ref);
+                }
+            }
+        }
+        selected
+    }
+}
+
+enum Format {
+    OverpassJson,
+    Xml,
+    Pbf,
+}
+
+impl Format {
+    fn detect(path: &str) -> Format {
+        let path = Path::new(path);
+        let file_name = path.file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if file_name.ends_with(".pbf") {
+            Format::Pbf
+        } else if file_name.ends_with(".osm") || file_name.ends_with(".xml") {
+            Format::Xml
+        } else {
+            Format::OverpassJson
+        }
+    }
+}
+
+/// A local OSM extract that is read once per record type.
+pub(crate) trait Extract {
+    /// Calls `f` for every record of `record_type`. Parts of the extract
+    /// without any of `ids` may be skipped.
+    fn for_each_record(
+        &mut self,
+        record_type: RecordType,
+        ids: Option<&BTreeSet<Id>>,
+        f: &mut dyn FnMut(Record),
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// Reads the selected relations and the ways and nodes they reference from a
+/// local extract, without keeping the rest of it in memory.
+fn read_extract(extract: &mut dyn Extract, filter: &RelationFilter) -> Result<Vec<Record>, Box<dyn Error>> {
+    let mut relations = vec![];
+    extract.for_each_record(RecordType::Relation, None, &mut |record| relations.push(record))?;
+    let selected = filter.selected_relations(relations.iter());
+    relations.retain(|record| selected.contains(&record.id));
+
+    let mut way_ids = BTreeSet::new();
+    let mut node_ids = BTreeSet::new();
+    for member in relations.iter().filter_map(|record| record.members.as_ref()).flatten() {
+        match member.record_type {
+            RecordType::Way => way_ids.insert(member.record_ref),
+            RecordType::Node => node_ids.insert(member.record_ref),
+            RecordType::Relation => false,
+        };
+    }
+
+    let mut ways = vec![];
+    extract.for_each_record(RecordType::Way, Some(&way_ids), &mut |record| {
+        if way_ids.contains(&record.id) {
+            node_ids.extend(record.nodes.iter().flatten().copied());
+            ways.push(record);
+        }
+    })?;
+
+    let mut nodes = vec![];
+    extract.for_each_record(RecordType::Node, Some(&node_ids), &mut |record| {
+        if node_ids.contains(&record.id) {
+            nodes.push(record);
+        }
+    })?;
+
+    Ok(nodes.into_iter().chain(ways).chain(relations).collect())
 }
 
 #[derive(Debug, Clone)]
 pub struct LineInfo {
-    pub line: Line,
+    /// R09 line number, if the OSM ref is numeric
+    pub line: Option<Line>,
+    /// public line ref from OSM, like "3" or "EV3"
+    pub reference: String,
     pub name: String,
-    // pub stops: Vec<LineStop>,
+    pub relation: Id,
+    pub route_master: Option<Id>,
+    pub stops: Vec<LineStop>,
     pub ways: Vec<Vec<Waypoint>>,
 }
 
@@ -93,8 +220,170 @@ impl PartialEq for Waypoint {
 
 impl Eq for Waypoint {}
 
+/// PTv2 distinguishes the position of the vehicle from the platform of the
+/// passengers.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StopKind {
+    StopPosition,
+    Platform,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineStop {
+    pub name: String,
+    pub kind: StopKind,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Recoverable issue found while assembling a route relation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    MissingRef,
+    MissingMember(RecordType, Id),
+    MissingNode { way: Id, node: Id },
+    UnknownRole { member: Id, role: String },
+    /// two consecutive ways of an ordered route do not share a node
+    Gap { after: Id, before: Id },
+    /// unordered ways that could not be joined without gluing
+    Discontiguities(usize),
+    /// neither the from nor the to stop tells which end a way chain starts at
+    UnknownDirection,
+    NoWays,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::MissingRef => write!(formatter, "no ref tag"),
+            Problem::MissingMember(record_type, id) => write!(formatter, "{:?} {} is missing in the export", record_type, id.0),
+            Problem::MissingNode { way, node } => write!(formatter, "node {} of way {} is missing in the export", node.0, way.0),
+            Problem::UnknownRole { member, role } => write!(formatter, "member {} has unknown role {:?}", member.0, role),
+            Problem::Gap { after, before } => write!(formatter, "gap between way {} and way {}", after.0, before.0),
+            Problem::Discontiguities(count) => write!(formatter, "{} discontiguities", count),
+            Problem::UnknownDirection => write!(formatter, "cannot tell the direction from the from and to stops"),
+            Problem::NoWays => write!(formatter, "no usable ways"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelationReport {
+    pub relation: Id,
+    pub name: Option<String>,
+    pub problems: Vec<Problem>,
+}
+
+impl fmt::Display for RelationReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "relation {}", self.relation.0)?;
+        if let Some(name) = &self.name {
+            write!(formatter, " ({})", name)?;
+        }
+        for problem in &self.problems {
+            write!(formatter, "\n  {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a member of a route relation is used for.
+enum MemberRole {
+    Way { backward: bool },
+    Stop(StopKind),
+    Ignored,
+    Unknown,
+}
+
+impl MemberRole {
+    fn of(member: &RelationMember) -> MemberRole {
+        match (member.record_type, member.role.as_str()) {
+            (_, "platform" | "platform_entry_only" | "platform_exit_only") => MemberRole::Stop(StopKind::Platform),
+            (RecordType::Node, "stop" | "stop_entry_only" | "stop_exit_only") => MemberRole::Stop(StopKind::StopPosition),
+            (RecordType::Way, "" | "forward" | "route") => MemberRole::Way { backward: false },
+            (RecordType::Way, "backward") => MemberRole::Way { backward: true },
+            // PTv1 stop areas and other grouping relations
+            (RecordType::Relation, _) => MemberRole::Ignored,
+            _ => MemberRole::Unknown,
+        }
+    }
+}
+
+fn is_closed(way: &[Waypoint]) -> bool {
+    way.len() > 2 && way.first() == way.last()
+}
+
+/// whether the route can continue from `waypoint` onto `way`
+fn touches(way: &[Waypoint], waypoint: &Waypoint) -> bool {
+    if is_closed(way) {
+        way.contains(waypoint)
+    } else {
+        way.first() == Some(waypoint) || way.last() == Some(waypoint)
+    }
+}
+
+/// Nodes of a roundabout from `entry` in driving direction until a node where
+/// the next way continues, or once around.
+fn roundabout_arc(ring: &[Waypoint], entry: &Waypoint, next: Option<&[Waypoint]>) -> Vec<Waypoint> {
+    let ring = &ring[..ring.len() - 1];
+    let start = ring.iter().position(|waypoint| waypoint == entry).unwrap_or(0);
+    let mut arc = vec![];
+    for offset in 1..=ring.len() {
+        let waypoint = &ring[(start + offset) % ring.len()];
+        arc.push(waypoint.clone());
+        if next.is_some_and(|next| !is_closed(next) && touches(next, waypoint)) {
+            break;
+        }
+    }
+    arc
+}
+
+/// Joins the ways of a PTv2 route in member order. Returns the joined chains
+/// and whether the orientation of the first way was a guess.
+fn assemble_ordered(ways: &[(Id, bool, Vec<Waypoint>)], problems: &mut Vec<Problem>) -> (Vec<Vec<Waypoint>>, bool) {
+    let mut chains = vec![];
+    let mut current: Vec<Waypoint> = vec![];
+    let mut guessed = false;
+
+    for (index, (id, backward, way)) in ways.iter().enumerate() {
+        let next = ways.get(index + 1).map(|(_, _, way)| &way[..]);
+
+        if let Some(end) = current.last().cloned() {
+            if is_closed(way) && way.contains(&end) {
+                current.extend(roundabout_arc(way, &end, next));
+                continue;
+            } else if way.first() == Some(&end) {
+                current.extend_from_slice(&way[1..]);
+                continue;
+            } else if way.last() == Some(&end) {
+                current.extend(way.iter().rev().skip(1).cloned());
+                continue;
+            }
+
+            problems.push(Problem::Gap { after: ways[index - 1].0, before: *id });
+            chains.push(std::mem::take(&mut current));
+        }
+
+        // start of a chain, orient the way towards the next one
+        let mut way = way.clone();
+        let connects_head = next.is_some_and(|next| touches(next, &way[0]));
+        let connects_tail = next.is_some_and(|next| touches(next, &way[way.len() - 1]));
+        if *backward || (connects_head && !connects_tail) {
+            way.reverse();
+        } else if chains.is_empty() && !connects_tail {
+            guessed = true;
+        }
+        current = way;
+    }
+
+    if !current.is_empty() {
+        chains.push(current);
+    }
+    (chains, guessed)
+}
+
 impl LineInfo {
-    fn detect_discontiguity(&self) -> bool {
+    fn count_discontiguities(&self) -> usize {
         let mut discontiguities = 0;
         for i in 1..self.ways.len() {
             if !self.ways[i - 1].is_empty() &&
@@ -105,12 +394,7 @@ impl LineInfo {
                 discontiguities += 1;
             }
         }
-        if discontiguities > 0 {
-            eprintln!("Line {}: {:?} contains {} discontiguities", self.line.0, self.name, discontiguities);
-            true
-        } else {
-            false
-        }
+        discontiguities
     }
 
     pub fn reorder_ways(&mut self) {
@@ -213,25 +497,219 @@ impl LineInfo {
         }
         self.ways = all_ways;
     }
+
+    /// Reverses ways whose head is far from the `from` stop of the route.
+    /// Unordered PTv1 routes do not tell their direction otherwise.
+    fn orient_by_from_to(&mut self, from: Option<&str>, to: Option<&str>, problems: &mut Vec<Problem>) {
+        let from_stop = from
+            .and_then(|from|
+                self.stops.iter()
+                    .map(|line_stop| (strsim::levenshtein(from, &line_stop.name), line_stop))
+                    .min_by_key(|(diff, _)| *diff)
+                    .map(|(_, line_stop)| line_stop)
+            );
+        let to_stop = to
+            .and_then(|to|
+                self.stops.iter()
+                    .find(|line_stop| line_stop.name == *to)
+            );
+
+        for ways in &mut self.ways {
+            let head_to_from = distance(&ways[0], from_stop);
+            let tail_to_from = distance(&ways[ways.len() - 1], from_stop);
+            let head_to_to = distance(&ways[0], to_stop);
+            let tail_to_to = distance(&ways[ways.len() - 1], to_stop);
+            match (head_to_from, head_to_to, tail_to_from, tail_to_to) {
+                (Some(head_to_from), _, Some(tail_to_from), _) if head_to_from > 10.0 * tail_to_from => {
+                    ways.reverse();
+                }
+                (_, Some(tail_to_from), _, Some(tail_to_to)) if 10.0 * tail_to_from < tail_to_to => {
+                    ways.reverse();
+                }
+                (Some(_), _, Some(_), _) |
+                (_, Some(_), _, Some(_)) => {}
+                _ => problems.push(Problem::UnknownDirection),
+            }
+        }
+    }
+
+    /// Reverses the first chain if the stops of the route run against it.
+    /// Only needed when a PTv2 route consists of a single way.
+    fn orient_by_stops(&mut self) {
+        let stop_positions = self.stops.iter()
+            .filter(|stop| stop.kind == StopKind::StopPosition)
+            .collect::<Vec<_>>();
+        let (Some(first), Some(last)) = (stop_positions.first(), stop_positions.last()) else {
+            return;
+        };
+        let Some(ways) = self.ways.first_mut() else {
+            return;
+        };
+        let head_to_first = distance(&ways[0], Some(first));
+        let tail_to_first = distance(&ways[ways.len() - 1], Some(first));
+        let head_to_last = distance(&ways[0], Some(last));
+        if tail_to_first < head_to_first && head_to_last < tail_to_first {
+            ways.reverse();
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct LineStop {
-    pub name: String,
-    pub lat: f64,
-    pub lon: f64,
+fn way_waypoints(way: &Record, records: &HashMap<(RecordType, Id), &Record>, problems: &mut Vec<Problem>) -> Vec<Waypoint> {
+    let Some(nodes) = &way.nodes else {
+        problems.push(Problem::MissingMember(RecordType::Way, way.id));
+        return vec![];
+    };
+    nodes.iter()
+        .filter_map(|id| {
+            let waypoint = records.get(&(RecordType::Node, *id))
+                .and_then(|record| record.waypoint());
+            if waypoint.is_none() {
+                problems.push(Problem::MissingNode { way: way.id, node: *id });
+            }
+            waypoint
+        })
+        .collect()
+}
+
+fn line_stop(
+    record: &Record,
+    kind: StopKind,
+    records: &HashMap<(RecordType, Id), &Record>,
+    problems: &mut Vec<Problem>,
+) -> Option<LineStop> {
+    let (lat, lon) = match record.record_type {
+        RecordType::Node => (record.lat?, record.lon?),
+        // platforms drawn as area or line, use their center
+        RecordType::Way => {
+            let waypoints = way_waypoints(record, records, problems);
+            if waypoints.is_empty() {
+                return None;
+            }
+            let count = waypoints.len() as f64;
+            (
+                waypoints.iter().map(|waypoint| waypoint.lat).sum::<f64>() / count,
+                waypoints.iter().map(|waypoint| waypoint.lon).sum::<f64>() / count,
+            )
+        }
+        RecordType::Relation => return None,
+    };
+    Some(LineStop {
+        name: record.tag("name").unwrap_or_default().to_string(),
+        kind,
+        lat,
+        lon,
+    })
+}
+
+/// Builds the `LineInfo` of one route relation, collecting recoverable
+/// problems on the way. Returns `None` if nothing usable is left.
+fn assemble_route(
+    relation: &Record,
+    records: &HashMap<(RecordType, Id), &Record>,
+    route_master: Option<&Record>,
+    problems: &mut Vec<Problem>,
+) -> Option<LineInfo> {
+    let reference = relation.tag("ref")
+        .or_else(|| route_master.and_then(|route_master| route_master.tag("ref")));
+    let Some(reference) = reference else {
+        problems.push(Problem::MissingRef);
+        return None;
+    };
+
+    let mut stops = vec![];
+    let mut ways = vec![];
+    for member in relation.members.iter().flatten() {
+        let Some(record) = records.get(&(member.record_type, member.record_ref)) else {
+            problems.push(Problem::MissingMember(member.record_type, member.record_ref));
+            continue;
+        };
+        match MemberRole::of(member) {
+            MemberRole::Way { backward } => {
+                let waypoints = way_waypoints(record, records, problems);
+                if waypoints.len() > 1 {
+                    ways.push((record.id, backward, waypoints));
+                }
+            }
+            MemberRole::Stop(kind) => stops.extend(line_stop(record, kind, records, problems)),
+            MemberRole::Ignored => {}
+            MemberRole::Unknown => problems.push(Problem::UnknownRole {
+                member: member.record_ref,
+                role: member.role.clone(),
+            }),
+        }
+    }
+    if ways.is_empty() {
+        problems.push(Problem::NoWays);
+        return None;
+    }
+
+    let mut info = LineInfo {
+        line: reference.parse().ok().map(Line::new),
+        reference: reference.to_string(),
+        name: relation.tag("name").map_or_else(|| format!("Linie {}", reference), std::string::ToString::to_string),
+        relation: relation.id,
+        route_master: route_master.map(|route_master| route_master.id),
+        stops,
+        ways: vec![],
+    };
+
+    if relation.tag("public_transport:version") == Some("2") {
+        let (chains, guessed) = assemble_ordered(&ways, problems);
+        info.ways = chains;
+        if guessed {
+            info.orient_by_stops();
+        }
+    } else {
+        info.ways = ways.into_iter()
+            .map(|(_, backward, mut way)| {
+                if backward {
+                    way.reverse();
+                }
+                way
+            })
+            .collect();
+        info.reorder_ways();
+        let discontiguities = info.count_discontiguities();
+        if discontiguities > 0 {
+            problems.push(Problem::Discontiguities(discontiguities));
+            info.glue_ways();
+        }
+        info.orient_by_from_to(relation.tag("from"), relation.tag("to"), problems);
+    }
+
+    Some(info)
 }
 
 pub fn read(path: &str) -> Result<Vec<LineInfo>, Box<dyn Error>> {
-    println!("reading osm export {}", path);
+    read_filtered(path, &RelationFilter::default())
+}
+
+/// Like `read_with_reports`, without the problems of the relations.
+pub fn read_filtered(path: &str, filter: &RelationFilter) -> Result<Vec<LineInfo>, Box<dyn Error>> {
+    Ok(read_with_reports(path, filter)?.0)
+}
+
+/// Reads the tram and bus routes from an Overpass JSON export, an `.osm`
+/// XML or an `.osm.pbf` extract, depending on the file extension.
+pub fn read_with_reports(
+    path: &str,
+    filter: &RelationFilter,
+) -> Result<(Vec<LineInfo>, Vec<RelationReport>), Box<dyn Error>> {
     let mut infos = vec![];
+    let mut reports = vec![];
 
-    let file = File::open(path)?;
-    let json: OverpassJson = serde_json::from_reader(file)?;
-    println!("{} osm primitives", json.elements.len());
+    let elements = match Format::detect(path) {
+        Format::OverpassJson => {
+            let file = File::open(path)?;
+            let json: OverpassJson = serde_json::from_reader(file)?;
+            json.elements
+        }
+        Format::Xml => read_extract(&mut osm_xml::XmlFile::new(path), filter)?,
+        Format::Pbf => read_extract(&mut osm_pbf::PbfFile::open(path)?, filter)?,
+    };
 
     let mut records = HashMap::new();
-    for record in &json.elements {
+    for record in &elements {
         let r = records.entry((record.record_type, record.id))
             .or_insert(record);
         // some records are more than one time in the json file, only
@@ -240,115 +718,44 @@ pub fn read(path: &str) -> Result<Vec<LineInfo>, Box<dyn Error>> {
             *r = record;
         }
     }
-    println!("{} records", records.len());
-
-    for record in &json.elements {
-        if record.record_type == RecordType::Relation {
-            if let (Some(_members), Some(tags)) = (&record.members, &record.tags) {
-                if [Some("route")].contains(&tags.get("type").map(std::string::String::as_str))
-                && [Some("tram"), Some("bus")].contains(&tags.get("route").map(std::string::String::as_str)) {
-                    let line = str::parse(tags.get("ref").expect("line ref"))
-                        .ok()
-                        .map(Line);
-                    if let Some(line) = line {
-                        let name = tags.get("name").map_or_else(|| format!("Linie {}", line.0), std::string::ToString::to_string);
-                        let stops = if let Some(members) = &record.members {
-                            members.iter().filter(|member| member.role == "stop")
-                                .filter_map(|member| {
-                                    assert_eq!(
-                                        records.get(&(member.record_type, member.record_ref))
-                                            .and_then(|record| record.line_stop()),
-                                        json.elements.iter()
-                                            .find(|record|
-                                                  record.record_type == member.record_type &&
-                                                  record.id == member.record_ref
-                                            ).and_then(Record::line_stop)
-                                    );
-                                    let result = records.get(&(member.record_type, member.record_ref))
-                                        .and_then(|record| record.line_stop());
-                                    if result.is_none() {
-                                        println!("Did not find stop with Id {}", member.record_ref.0);
-                                    }
-                                    result
-                                }).collect()
-                        } else {
-                            vec![]
-                        };
-                        dbg!(stops.len());
-                        let from_stop = tags.get("from")
-                            .and_then(|from|
-                                stops.iter()
-                                    .map(|line_stop| (strsim::levenshtein(from, &line_stop.name), line_stop))
-                                    .min_by_key(|(diff, _)| *diff)
-                                    .map(|(_, line_stop)| line_stop)
-                            );
-                        let to_stop = tags.get("to")
-                            .and_then(|to|
-                                stops.iter()
-                                    .find(|line_stop| line_stop.name == *to)
-                            );
-                        dbg!(from_stop, to_stop);
-                        let ways = if let Some(members) = &record.members {
-                            members.iter().filter(|member| member.record_type == RecordType::Way && member.role.is_empty())
-                                .map(|member| {
-                                    records.get(&(member.record_type, member.record_ref))
-                                        .expect("way")
-                                })
-                                .map(|way| {
-                                    way.nodes.as_ref().expect("way.nodes")
-                                        .iter().map(|id| {
-                                            records.get(&(RecordType::Node, *id))
-                                                .and_then(|record| record.waypoint())
-                                                .expect("way node")
-                                        }).collect()
-                                })
-                                .collect()
-                        } else {
-                            vec![]
-                        };
-                        let mut info = LineInfo {
-                            line,
-                            name,
-                            // stops,
-                            ways,
-                        };
-                        info.reorder_ways();
-                        if info.detect_discontiguity() {
-                            println!("gluing");
-                            info.glue_ways();
-                            info.detect_discontiguity();
-                        }
-
-                        for ways in &mut info.ways {
-                            let head_to_from = distance(&ways[0], from_stop);
-                            let tail_to_from = distance(&ways[ways.len() - 1], from_stop);
-                            let head_to_to = distance(&ways[0], to_stop);
-                            let tail_to_to = distance(&ways[ways.len() - 1], to_stop);
-                            match (head_to_from, head_to_to, tail_to_from, tail_to_to) {
-                                (Some(head_to_from), _, Some(tail_to_from), _) if head_to_from > 10.0 * tail_to_from => {
-                                    println!("Reversing ({:.0}m > {:.0}m)", head_to_from, tail_to_from);
-                                    ways.reverse();
-                                }
-                                (_, Some(tail_to_from), _, Some(tail_to_to)) if 10.0 * tail_to_from < tail_to_to => {
-                                    println!("Reversing ({:.0}m < {:.0}m)", tail_to_from, tail_to_to);
-                                    ways.reverse();
-                                }
-                                (Some(_), _, Some(_), _) |
-                                (_, Some(_), _, Some(_)) => {}
-                                _ => {
-                                    println!("Trouble finding exits for {}", line.0);
-                                }
-                            }
-                        }
 
-                        infos.push(info);
-                    }
-                }
+    let mut route_masters = HashMap::new();
+    for record in records.values() {
+        if record.record_type == RecordType::Relation && record.tag("type") == Some("route_master") {
+            for member in record.members.iter().flatten() {
+                route_masters.insert(member.record_ref, *record);
             }
         }
     }
 
-    Ok(infos)
+    let selected = filter.selected_relations(elements.iter());
+    let mut done = HashSet::new();
+    for record in &elements {
+        if record.record_type != RecordType::Relation
+            || !selected.contains(&record.id)
+            || !done.insert(record.id)
+        {
+            continue;
+        }
+        let record = records[&(RecordType::Relation, record.id)];
+        if record.tag("type") != Some("route") || !["tram", "bus"].contains(&record.tag("route").unwrap_or_default()) {
+            continue;
+        }
+
+        let mut problems = vec![];
+        if let Some(info) = assemble_route(record, &records, route_masters.get(&record.id).copied(), &mut problems) {
+            infos.push(info);
+        }
+        if !problems.is_empty() {
+            reports.push(RelationReport {
+                relation: record.id,
+                name: record.tag("name").map(std::string::ToString::to_string),
+                problems,
+            });
+        }
+    }
+
+    Ok((infos, reports))
 }
 
 fn distance(way: &Waypoint, line_stop: Option<&LineStop>) -> Option<f64> {
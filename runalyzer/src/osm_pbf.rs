@@ -0,0 +1,429 @@
+//! Minimal reader for the OSM PBF format, see
+//! <https://wiki.openstreetmap.org/wiki/PBF_Format>. Only the fields needed
+//! for route relations are decoded.
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use flate2::read::ZlibDecoder;
+use super::osm_lines::{Extract, Id, Record, RecordType, RelationMember};
+
+/// largest BlobHeader the format allows
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+/// largest Blob the format allows, compressed and uncompressed
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<u64, Box<dyn Error>> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos).ok_or("truncated varint")?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("varint too long".into())
+}
+
+/// the `len` bytes at `pos`
+fn read_bytes<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+    let end = pos.checked_add(len).ok_or("field too long")?;
+    let bytes = data.get(*pos..end).ok_or("truncated field")?;
+    *pos = end;
+    Ok(bytes)
+}
+
+/// decodes the fields of a protobuf message
+fn fields(data: &[u8]) -> Result<Vec<(u64, Value<'_>)>, Box<dyn Error>> {
+    let mut fields = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = read_varint(data, &mut pos)?;
+        let value = match key & 7 {
+            0 => Value::Varint(read_varint(data, &mut pos)?),
+            1 => {
+                read_bytes(data, &mut pos, 8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = usize::try_from(read_varint(data, &mut pos)?)?;
+                Value::Bytes(read_bytes(data, &mut pos, len)?)
+            }
+            5 => {
+                read_bytes(data, &mut pos, 4)?;
+                Value::Fixed
+            }
+            wire_type => return Err(format!("unsupported wire type {}", wire_type).into()),
+        };
+        fields.push((key >> 3, value));
+    }
+    Ok(fields)
+}
+
+fn zigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// appends packed or unpacked repeated varints
+fn push_varints(values: &mut Vec<u64>, value: &Value) -> Result<(), Box<dyn Error>> {
+    match value {
+        Value::Varint(value) => values.push(*value),
+        Value::Bytes(data) => {
+            let mut pos = 0;
+            while pos < data.len() {
+                values.push(read_varint(data, &mut pos)?);
+            }
+        }
+        Value::Fixed => {}
+    }
+    Ok(())
+}
+
+/// delta decoded sint64 values
+fn delta_decode(values: &[u64]) -> Result<Vec<i64>, Box<dyn Error>> {
+    let mut last = 0i64;
+    values.iter()
+        .map(|value| {
+            last = last.checked_add(zigzag(*value)).ok_or("delta coded value out of range")?;
+            Ok(last)
+        })
+        .collect()
+}
+
+struct Block<'a> {
+    strings: Vec<&'a [u8]>,
+    granularity: i64,
+    lat_offset: i64,
+    lon_offset: i64,
+}
+
+impl Block<'_> {
+    fn string(&self, index: u64) -> String {
+        usize::try_from(index).ok()
+            .and_then(|index| self.strings.get(index))
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .unwrap_or_default()
+    }
+
+    fn tags(&self, keys: &[u64], values: &[u64]) -> Option<HashMap<String, String>> {
+        if keys.is_empty() {
+            return None;
+        }
+        Some(keys.iter().zip(values)
+            .map(|(key, value)| (self.string(*key), self.string(*value)))
+            .collect())
+    }
+
+    fn coordinate(&self, offset: i64, value: i64) -> Result<f64, Box<dyn Error>> {
+        let nanodegrees = self.granularity.checked_mul(value)
+            .and_then(|value| value.checked_add(offset))
+            .ok_or("coordinate out of range")?;
+        Ok(1e-9 * nanodegrees as f64)
+    }
+
+    fn node(&self, id: i64, lat: i64, lon: i64, tags: Option<HashMap<String, String>>) -> Result<Record, Box<dyn Error>> {
+        Ok(Record {
+            record_type: RecordType::Node,
+            id: Id(id),
+            lat: Some(self.coordinate(self.lat_offset, lat)?),
+            lon: Some(self.coordinate(self.lon_offset, lon)?),
+            nodes: None,
+            members: None,
+            tags,
+        })
+    }
+}
+
+/// Lowest and highest id of every record type in a block.
+#[derive(Debug, Clone, Default)]
+struct Contents(Vec<(RecordType, i64, i64)>);
+
+impl Contents {
+    fn add(&mut self, record_type: RecordType, id: i64) {
+        match self.0.iter_mut().find(|(contained, _, _)| *contained == record_type) {
+            Some((_, lowest, highest)) => {
+                *lowest = id.min(*lowest);
+                *highest = id.max(*highest);
+            }
+            None => self.0.push((record_type, id, id)),
+        }
+    }
+
+    /// whether the block may hold records of `record_type` with one of `ids`
+    fn may_contain(&self, record_type: RecordType, ids: Option<&BTreeSet<Id>>) -> bool {
+        self.0.iter().any(|(contained, lowest, highest)| {
+            *contained == record_type &&
+            ids.is_none_or(|ids| ids.range(Id(*lowest)..=Id(*highest)).next().is_some())
+        })
+    }
+}
+
+/// Calls `f` for the records of `record_type` in the block and returns the
+/// ids of all records in it.
+fn read_primitive_block(
+    data: &[u8],
+    record_type: RecordType,
+    f: &mut dyn FnMut(Record),
+) -> Result<Contents, Box<dyn Error>> {
+    let mut block = Block {
+        strings: vec![],
+        granularity: 100,
+        lat_offset: 0,
+        lon_offset: 0,
+    };
+    let mut groups = vec![];
+    for (field, value) in fields(data)? {
+        match (field, value) {
+            (1, Value::Bytes(string_table)) => {
+                for (field, value) in fields(string_table)? {
+                    if let (1, Value::Bytes(s)) = (field, value) {
+                        block.strings.push(s);
+                    }
+                }
+            }
+            (2, Value::Bytes(group)) => groups.push(group),
+            (17, Value::Varint(granularity)) => block.granularity = granularity as i64,
+            (19, Value::Varint(offset)) => block.lat_offset = offset as i64,
+            (20, Value::Varint(offset)) => block.lon_offset = offset as i64,
+            _ => {}
+        }
+    }
+
+    let mut contents = Contents::default();
+    let mut found = |record: Record, contents: &mut Contents| {
+        contents.add(record.record_type, record.id.0);
+        if record.record_type == record_type {
+            f(record);
+        }
+    };
+    for group in groups {
+        for (field, value) in fields(group)? {
+            let Value::Bytes(data) = value else {
+                continue;
+            };
+            match field {
+                1 => found(read_node(&block, data)?, &mut contents),
+                2 => read_dense_nodes(&block, data, &mut |record| found(record, &mut contents))?,
+                3 => found(read_way(&block, data)?, &mut contents),
+                4 => found(read_relation(&block, data)?, &mut contents),
+                _ => {}
+            }
+        }
+    }
+    Ok(contents)
+}
+
+fn read_node(block: &Block, data: &[u8]) -> Result<Record, Box<dyn Error>> {
+    let (mut id, mut lat, mut lon) = (0, 0, 0);
+    let (mut keys, mut values) = (vec![], vec![]);
+    for (field, value) in fields(data)? {
+        match (field, &value) {
+            (1, Value::Varint(v)) => id = zigzag(*v),
+            (2, _) => push_varints(&mut keys, &value)?,
+            (3, _) => push_varints(&mut values, &value)?,
+            (8, Value::Varint(v)) => lat = zigzag(*v),
+            (9, Value::Varint(v)) => lon = zigzag(*v),
+            _ => {}
+        }
+    }
+    block.node(id, lat, lon, block.tags(&keys, &values))
+}
+
+fn read_dense_nodes(block: &Block, data: &[u8], f: &mut dyn FnMut(Record)) -> Result<(), Box<dyn Error>> {
+    let (mut ids, mut lats, mut lons, mut keys_values) = (vec![], vec![], vec![], vec![]);
+    for (field, value) in fields(data)? {
+        match field {
+            1 => push_varints(&mut ids, &value)?,
+            8 => push_varints(&mut lats, &value)?,
+            9 => push_varints(&mut lons, &value)?,
+            10 => push_varints(&mut keys_values, &value)?,
+            _ => {}
+        }
+    }
+
+    // keys_values holds key value pairs for each node, terminated by 0
+    let mut keys_values = keys_values.into_iter();
+    for ((id, lat), lon) in delta_decode(&ids)?.into_iter()
+        .zip(delta_decode(&lats)?)
+        .zip(delta_decode(&lons)?)
+    {
+        let (mut keys, mut values) = (vec![], vec![]);
+        while let Some(key) = keys_values.next() {
+            if key == 0 {
+                break;
+            }
+            keys.push(key);
+            values.push(keys_values.next().ok_or("truncated dense node tags")?);
+        }
+        f(block.node(id, lat, lon, block.tags(&keys, &values))?);
+    }
+    Ok(())
+}
+
+fn read_way(block: &Block, data: &[u8]) -> Result<Record, Box<dyn Error>> {
+    let mut id = 0;
+    let (mut keys, mut values, mut refs) = (vec![], vec![], vec![]);
+    for (field, value) in fields(data)? {
+        match (field, &value) {
+            (1, Value::Varint(v)) => id = *v as i64,
+            (2, _) => push_varints(&mut keys, &value)?,
+            (3, _) => push_varints(&mut values, &value)?,
+            (8, _) => push_varints(&mut refs, &value)?,
+            _ => {}
+        }
+    }
+    Ok(Record {
+        record_type: RecordType::Way,
+        id: Id(id),
+        lat: None,
+        lon: None,
+        nodes: Some(delta_decode(&refs)?.into_iter().map(Id).collect()),
+        members: None,
+        tags: block.tags(&keys, &values),
+    })
+}
+
+fn read_relation(block: &Block, data: &[u8]) -> Result<Record, Box<dyn Error>> {
+    let mut id = 0;
+    let (mut keys, mut values, mut roles, mut member_ids, mut types) = (vec![], vec![], vec![], vec![], vec![]);
+    for (field, value) in fields(data)? {
+        match (field, &value) {
+            (1, Value::Varint(v)) => id = *v as i64,
+            (2, _) => push_varints(&mut keys, &value)?,
+            (3, _) => push_varints(&mut values, &value)?,
+            (8, _) => push_varints(&mut roles, &value)?,
+            (9, _) => push_varints(&mut member_ids, &value)?,
+            (10, _) => push_varints(&mut types, &value)?,
+            _ => {}
+        }
+    }
+    let members = delta_decode(&member_ids)?.into_iter()
+        .zip(types)
+        .zip(roles)
+        .filter_map(|((member_id, member_type), role)| {
+            let record_type = match member_type {
+                0 => RecordType::Node,
+                1 => RecordType::Way,
+                2 => RecordType::Relation,
+                _ => return None,
+            };
+            Some(RelationMember {
+                record_type,
+                record_ref: Id(member_id),
+                role: block.string(role),
+            })
+        })
+        .collect();
+    Ok(Record {
+        record_type: RecordType::Relation,
+        id: Id(id),
+        lat: None,
+        lon: None,
+        nodes: None,
+        members: Some(members),
+        tags: block.tags(&keys, &values),
+    })
+}
+
+/// the uncompressed data of a blob
+fn decompress(blob: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    for (field, value) in fields(blob)? {
+        match (field, value) {
+            (1, Value::Bytes(raw)) => return Ok(raw.to_vec()),
+            (3, Value::Bytes(zlib_data)) => {
+                let mut raw = vec![];
+                ZlibDecoder::new(zlib_data)
+                    .take(MAX_BLOB_SIZE as u64 + 1)
+                    .read_to_end(&mut raw)?;
+                if raw.len() > MAX_BLOB_SIZE {
+                    return Err("uncompressed blob too large".into());
+                }
+                return Ok(raw);
+            }
+            _ => {}
+        }
+    }
+    Err("unsupported blob compression".into())
+}
+
+/// An OSMData blob in the file, with the records it holds once it has been
+/// decoded.
+struct Blob {
+    offset: u64,
+    size: usize,
+    contents: Option<Contents>,
+}
+
+/// An `.osm.pbf` file. The first pass over it decodes every blob, later ones
+/// only those that hold the requested records.
+pub(crate) struct PbfFile {
+    file: BufReader<File>,
+    blobs: Vec<Blob>,
+}
+
+impl PbfFile {
+    /// Reads the blob headers, the blobs are decoded when records are read.
+    pub(crate) fn open(path: &str) -> Result<PbfFile, Box<dyn Error>> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut blobs = vec![];
+        loop {
+            let mut header_size = [0; 4];
+            match file.read_exact(&mut header_size) {
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                result => result?,
+            }
+            let header_size = u32::from_be_bytes(header_size) as usize;
+            if header_size > MAX_HEADER_SIZE {
+                return Err(format!("blob header of {} bytes is too large", header_size).into());
+            }
+            let mut header = vec![0; header_size];
+            file.read_exact(&mut header)?;
+
+            let mut blob_type = String::new();
+            let mut blob_size = 0;
+            for (field, value) in fields(&header)? {
+                match (field, value) {
+                    (1, Value::Bytes(s)) => blob_type = String::from_utf8_lossy(s).into_owned(),
+                    (3, Value::Varint(size)) => blob_size = usize::try_from(size)?,
+                    _ => {}
+                }
+            }
+            if blob_size > MAX_BLOB_SIZE {
+                return Err(format!("blob of {} bytes is too large", blob_size).into());
+            }
+            let offset = file.stream_position()?;
+            file.seek_relative(blob_size as i64)?;
+            if blob_type == "OSMData" {
+                blobs.push(Blob { offset, size: blob_size, contents: None });
+            }
+        }
+        Ok(PbfFile { file, blobs })
+    }
+}
+
+impl Extract for PbfFile {
+    fn for_each_record(
+        &mut self,
+        record_type: RecordType,
+        ids: Option<&BTreeSet<Id>>,
+        f: &mut dyn FnMut(Record),
+    ) -> Result<(), Box<dyn Error>> {
+        for blob in &mut self.blobs {
+            if blob.contents.as_ref().is_some_and(|contents| !contents.may_contain(record_type, ids)) {
+                continue;
+            }
+            let mut data = vec![0; blob.size];
+            self.file.seek(SeekFrom::Start(blob.offset))?;
+            self.file.read_exact(&mut data)?;
+            blob.contents = Some(read_primitive_block(&decompress(&data)?, record_type, f)?);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use super::osm_lines::{Extract, Id, Record, RecordType, RelationMember};
+
+fn attributes(element: &BytesStart) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut attributes = HashMap::new();
+    for attribute in element.attributes() {
+        let attribute = attribute?;
+        attributes.insert(
+            String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+            attribute.unescape_value()?.into_owned(),
+        );
+    }
+    Ok(attributes)
+}
+
+fn record_type(name: &str) -> Option<RecordType> {
+    match name {
+        "node" => Some(RecordType::Node),
+        "way" => Some(RecordType::Way),
+        "relation" => Some(RecordType::Relation),
+        _ => None,
+    }
+}
+
+fn parse<T: std::str::FromStr>(attributes: &HashMap<String, String>, key: &str) -> Option<T> {
+    attributes.get(key)?.parse().ok()
+}
+
+/// An `.osm` XML file, read from the start for every record type.
+pub(crate) struct XmlFile<'a> {
+    path: &'a str,
+}
+
+impl XmlFile<'_> {
+    pub(crate) fn new(path: &str) -> XmlFile<'_> {
+        XmlFile { path }
+    }
+}
+
+impl Extract for XmlFile<'_> {
+    fn for_each_record(
+        &mut self,
+        record_type: RecordType,
+        _ids: Option<&BTreeSet<Id>>,
+        f: &mut dyn FnMut(Record),
+    ) -> Result<(), Box<dyn Error>> {
+        for_each_record(self.path, record_type, f)
+    }
+}
+
+/// Calls `f` for every record of `record_type` in an `.osm` XML file.
+fn for_each_record(
+    path: &str,
+    record_type: RecordType,
+    f: &mut dyn FnMut(Record),
+) -> Result<(), Box<dyn Error>> {
+    let mut reader = Reader::from_file(path)?;
+    let mut buf = vec![];
+    let mut current: Option<Record> = None;
+
+    loop {
+        let (element, is_empty) = match reader.read_event_into(&mut buf)? {
+            Event::Eof => break,
+            Event::Start(element) => (element, false),
+            Event::Empty(element) => (element, true),
+            Event::End(element) => {
+                let name = String::from_utf8_lossy(element.name().as_ref()).into_owned();
+                if record_type_matches(&name, record_type) {
+                    if let Some(record) = current.take() {
+                        f(record);
+                    }
+                }
+                buf.clear();
+                continue;
+            }
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+
+        let name = String::from_utf8_lossy(element.name().as_ref()).into_owned();
+        let attributes = attributes(&element)?;
+        match (name.as_str(), current.as_mut()) {
+            (_, None) if record_type_matches(&name, record_type) => {
+                let record = Record {
+                    record_type,
+                    id: Id(parse(&attributes, "id").ok_or("osm element without id")?),
+                    lat: parse(&attributes, "lat"),
+                    lon: parse(&attributes, "lon"),
+                    nodes: None,
+                    members: None,
+                    tags: None,
+                };
+                if is_empty {
+                    f(record);
+                } else {
+                    current = Some(record);
+                }
+            }
+            ("tag", Some(record)) => {
+                if let (Some(key), Some(value)) = (attributes.get("k"), attributes.get("v")) {
+                    record.tags.get_or_insert_with(HashMap::new)
+                        .insert(key.clone(), value.clone());
+                }
+            }
+            ("nd", Some(record)) => {
+                if let Some(node) = parse(&attributes, "ref") {
+                    record.nodes.get_or_insert_with(Vec::new)
+                        .push(Id(node));
+                }
+            }
+            ("member", Some(record)) => {
+                let member_type = attributes.get("type")
+                    .and_then(|member_type| self::record_type(member_type));
+                if let (Some(member_type), Some(member_ref)) = (member_type, parse(&attributes, "ref")) {
+                    record.members.get_or_insert_with(Vec::new)
+                        .push(RelationMember {
+                            record_type: member_type,
+                            record_ref: Id(member_ref),
+                            role: attributes.get("role").cloned().unwrap_or_default(),
+                        });
+                }
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    Ok(())
+}
+
+fn record_type_matches(name: &str, record_type: RecordType) -> bool {
+    self::record_type(name) == Some(record_type)
+}
@@ -0,0 +1,130 @@
+//! Line files written by `runalyzer segmentize`.
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use stop_names::DocumentMetaInformation;
+use super::osm_lines::Id;
+use super::{Junction, Line};
+
+/// version of the line file format
+pub const SCHEMA_VERSION: &str = "1.0";
+
+/// document header of files generated by runalyzer
+pub fn document(schema_version: &str) -> DocumentMetaInformation {
+    DocumentMetaInformation {
+        schema_version: schema_version.to_string(),
+        date: Utc::now(),
+        generator: Some("runalyzer".to_string()),
+        generator_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+    }
+}
+
+/// All analyzed OSM route variants of a line.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LineDocument {
+    pub document: DocumentMetaInformation,
+    pub line: Line,
+    pub variants: Vec<RouteVariant>,
+}
+
+/// One OSM route relation with the known stops in driving order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RouteVariant {
+    pub name: String,
+    pub relation: Id,
+    pub stops: Vec<VariantStop>,
+    pub segments: Vec<TrackSegment>,
+}
+
+/// Known stop of stops.json that lies on the route variant.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VariantStop {
+    pub junction: Junction,
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Track between two consecutive known stops.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackSegment {
+    pub start: Junction,
+    pub stop: Junction,
+    /// `[lon, lat]` pairs like in GeoJSON
+    pub geometry: Vec<[f64; 2]>,
+    /// meters
+    pub length: f64,
+    /// expected seconds from start to stop
+    pub duration: f64,
+    /// all junctions including start and stop
+    pub junctions: Vec<PlacedJunction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlacedJunction {
+    pub junction: Junction,
+    pub lat: f64,
+    pub lon: f64,
+    /// meters from the start of the segment
+    pub along: f64,
+    /// expected seconds from the start of the segment
+    pub time: f64,
+}
+
+impl LineDocument {
+    pub fn new(line: Line, variants: Vec<RouteVariant>) -> LineDocument {
+        LineDocument {
+            document: document(SCHEMA_VERSION),
+            line,
+            variants,
+        }
+    }
+
+    /// GeoJSON FeatureCollection with a LineString per segment and a Point
+    /// per placed junction
+    pub fn geojson(&self) -> Value {
+        let mut features = vec![];
+        for variant in &self.variants {
+            for segment in &variant.segments {
+                features.push(json!({
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "LineString",
+                        "coordinates": segment.geometry,
+                    },
+                    "properties": {
+                        "line": self.line.to_string(),
+                        "variant": variant.name,
+                        "start": segment.start,
+                        "stop": segment.stop,
+                        "length": segment.length,
+                        "duration": segment.duration,
+                    },
+                }));
+                for placed in &segment.junctions {
+                    features.push(json!({
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "Point",
+                            "coordinates": [placed.lon, placed.lat],
+                        },
+                        "properties": {
+                            "line": self.line.to_string(),
+                            "variant": variant.name,
+                            "junction": placed.junction,
+                            "known": placed.junction == segment.start || placed.junction == segment.stop,
+                            "along": placed.along,
+                            "time": placed.time,
+                        },
+                    }));
+                }
+            }
+        }
+
+        json!({
+            "type": "FeatureCollection",
+            "document": self.document,
+            "features": features,
+        })
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use geo::Point;
+use super::known_stops::Stop;
+use super::osm_lines::LineInfo;
+use super::output::{RouteVariant, VariantStop};
+use super::segments::{self, Segment};
+use super::{Junction, Line, LineRun};
+
+/// Durations between the junctions of a run, grouped by the pair of known
+/// stops they lie between.
+pub type RunSegment = ((Junction, Junction), Vec<(Duration, Junction)>);
+
+/// What `analyze_line` found for a route variant.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineReport {
+    pub name: String,
+    pub known_stops: usize,
+    pub matching_runs: usize,
+    /// junctions placed between the known stops
+    pub new_junctions: usize,
+    /// consecutive junctions without a shortest duration
+    pub missing_durations: usize,
+    /// junctions that could not be placed on the ways of the line
+    pub unplaced_junctions: usize,
+}
+
+impl fmt::Display for LineReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            formatter,
+            "{}: {} known stops, {} matching runs, {} new junctions",
+            self.name, self.known_stops, self.matching_runs, self.new_junctions,
+        )?;
+        if self.missing_durations > 0 {
+            write!(formatter, ", {} without best duration", self.missing_durations)?;
+        }
+        if self.unplaced_junctions > 0 {
+            write!(formatter, ", {} not placed", self.unplaced_junctions)?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the known stops that lie on the ways of a line, ordered along the
+/// ways. The `usize` is the index of the closest way line.
+pub fn find_known_stops(
+    stops: &HashMap<Junction, Stop>,
+    line_info: &LineInfo,
+    max_distance: f64,
+) -> Vec<(usize, Junction, Point<f64>)> {
+    let mut line_known_stops = stops.iter().filter_map(|(junction, stop)| {
+        let known_point = Point::new(stop.lon, stop.lat);
+        segments::way_point(&line_info.ways, &known_point, max_distance)
+            .map(|(index, point)| (index, *junction, point))
+    }).collect::<Vec<_>>();
+    line_known_stops.sort_by_key(|(index, _, _)| *index);
+    line_known_stops
+}
+
+/// Checks that the junctions of `partial` appear in `goal` in the same
+/// order, ignoring junctions of `goal` that are missing in `partial`.
+pub fn is_similar_sequence(partial: &[Junction], goal: &[Junction]) -> bool {
+    let partial_set = partial.iter().collect::<HashSet<_>>();
+    let partial_of_goal = goal.iter()
+        .filter(|g| partial_set.contains(g))
+        .copied()
+        .collect::<Vec<_>>();
+    partial_of_goal[..] == *partial
+}
+
+/// Selects the runs of `line` that pass at least two of the known stops of
+/// a route variant in its order.
+pub fn matching_runs<'a>(
+    line: Line,
+    known_stop_junctions: &[Junction],
+    junctions_by_known_stops: &'a [(LineRun, Vec<Junction>, Vec<(SystemTime, Junction)>)],
+) -> Vec<&'a [(SystemTime, Junction)]> {
+    junctions_by_known_stops.iter()
+        .filter(|(line_run, known_junctions, _)|
+            line_run.line == line &&
+            known_junctions.len() > 1 &&
+            is_similar_sequence(known_junctions, known_stop_junctions)
+        )
+        .map(|(_, _, junctions)| &junctions[..])
+        .collect()
+}
+
+/// Splits all runs into the segments between their known stops.
+pub fn run_segments(
+    known_stops: &HashSet<Junction>,
+    runs: &[&[(SystemTime, Junction)]],
+) -> Vec<RunSegment> {
+    runs.iter()
+        .flat_map(|junctions| segments::segment_run_by_known_stops(known_stops, junctions))
+        .collect()
+}
+
+/// Keeps the segment with the most junctions for every pair of known stops.
+pub fn longest_segments(run_segments: &[RunSegment]) -> HashMap<(Junction, Junction), Vec<(Duration, Junction)>> {
+    let mut longest_segments = HashMap::new();
+    for ((start, stop), segment) in run_segments {
+        let longest_segment = longest_segments.entry((*start, *stop))
+            .or_insert_with(|| segment.clone());
+        if longest_segment.len() < segment.len() {
+            *longest_segment = segment.clone();
+        }
+    }
+    longest_segments
+}
+
+/// Shortest observed duration between every pair of consecutive junctions.
+pub fn min_durations(run_segments: &[RunSegment]) -> HashMap<(Junction, Junction), Duration> {
+    let mut min_durations = HashMap::new();
+    for (_, segment) in run_segments {
+        let mut last_junction = None;
+        for (duration, junction) in segment {
+            if let Some(last_junction) = last_junction.take() {
+                let min_duration = min_durations.entry((last_junction, *junction))
+                    .or_insert(*duration);
+                if duration < min_duration {
+                    *min_duration = *duration;
+                }
+            }
+
+            last_junction = Some(*junction);
+        }
+    }
+    min_durations
+}
+
+/// Replaces the durations of each segment by the shortest ones observed,
+/// also returns the number of junction pairs that keep their duration as
+/// none was observed.
+pub fn apply_min_durations(
+    longest_segments: HashMap<(Junction, Junction), Vec<(Duration, Junction)>>,
+    min_durations: &HashMap<(Junction, Junction), Duration>,
+) -> (HashMap<(Junction, Junction), Vec<(Duration, Junction)>>, usize) {
+    let mut missing = 0;
+    let segments = longest_segments.into_iter()
+        .map(|((start, stop), segment)| {
+            let mut last_junction = None;
+            let mut min_segment = Vec::with_capacity(segment.len());
+            for (duration, junction) in segment {
+                let result = if let Some(last_junction) = last_junction {
+                    if let Some(min_duration) = min_durations.get(&(last_junction, junction)) {
+                        (*min_duration, junction)
+                    } else {
+                        missing += 1;
+                        (duration, junction)
+                    }
+                } else {
+                    (duration, junction)
+                };
+                min_segment.push(result);
+
+                last_junction = Some(junction);
+            }
+            ((start, stop), min_segment)
+        })
+        .collect();
+    (segments, missing)
+}
+
+/// Builds a `Segment` for every pair of consecutive known stops along the
+/// line that has timing information.
+pub fn known_stop_segments(
+    line_known_stops: &[(usize, Junction, Point<f64>)],
+    segments: &HashMap<(Junction, Junction), Vec<(Duration, Junction)>>,
+) -> Vec<Segment> {
+    line_known_stops.windows(2)
+        .filter_map(|pair| {
+            let (_, start, start_point) = pair[0];
+            let (_, stop, stop_point) = pair[1];
+            segments.get(&(start, stop))
+                .map(|segment| Segment {
+                    start: (start, start_point),
+                    stop: (stop, stop_point),
+                    junctions: segments::to_rational(segment),
+                    duration: segment.iter().map(|(duration, _)| *duration).sum(),
+                })
+        })
+        .collect()
+}
+
+/// Runs all stages for one OSM route variant. The variant is `None` if it
+/// has no R09 line number or fewer than two known stops lie on its ways.
+pub fn analyze_line(
+    line_info: LineInfo,
+    stops: &HashMap<Junction, Stop>,
+    junctions_by_known_stops: &[(LineRun, Vec<Junction>, Vec<(SystemTime, Junction)>)],
+    max_distance: f64,
+) -> (Option<RouteVariant>, LineReport) {
+    let mut report = LineReport {
+        name: line_info.name.clone(),
+        ..Default::default()
+    };
+    let Some(line) = line_info.line.clone() else {
+        return (None, report);
+    };
+    let known_stops = stops.keys().copied().collect::<HashSet<_>>();
+    let line_known_stops = find_known_stops(stops, &line_info, max_distance);
+    report.known_stops = line_known_stops.len();
+    if line_known_stops.len() < 2 {
+        return (None, report);
+    }
+
+    let known_stop_junctions = line_known_stops.iter()
+        .map(|(_, junction, _)| junction)
+        .copied()
+        .collect::<Vec<Junction>>();
+    let matching_runs = matching_runs(
+        line,
+        &known_stop_junctions,
+        junctions_by_known_stops,
+    );
+    report.matching_runs = matching_runs.len();
+
+    let run_segments = run_segments(&known_stops, &matching_runs);
+    let (segments, missing_durations) = apply_min_durations(
+        longest_segments(&run_segments),
+        &min_durations(&run_segments),
+    );
+    report.missing_durations = missing_durations;
+    let known_stop_segments = known_stop_segments(&line_known_stops, &segments);
+
+    report.new_junctions = known_stop_segments.iter().map(|segment|
+        segment.junctions.iter().filter(|(_, junction)|
+            *junction != segment.start.0 &&
+            *junction != segment.stop.0
+        ).count()
+    ).sum();
+
+    let mut segment_results = vec![];
+    for segment in &known_stop_segments {
+        let result = segments::segmentize(segment, &line_info.ways);
+        let placed = result.as_ref().map_or(0, |result| result.junctions.len());
+        report.unplaced_junctions += segment.junctions.len() - placed;
+        segment_results.extend(result);
+    }
+    let variant = RouteVariant {
+        name: line_info.name,
+        relation: line_info.relation,
+        stops: line_known_stops.iter()
+            .map(|(_, junction, _)| {
+                let stop = &stops[junction];
+                VariantStop {
+                    junction: *junction,
+                    name: stop.name.clone(),
+                    lat: stop.lat,
+                    lon: stop.lon,
+                }
+            })
+            .collect(),
+        segments: segment_results,
+    };
+    (Some(variant), report)
+}
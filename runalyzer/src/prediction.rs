@@ -0,0 +1,188 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::{Duration, SystemTime};
+use stop_names::RegionGraph;
+use super::{Junction, Line, LineRun, Run};
+
+/// quantiles of the observed hop durations used as bounds of an `Eta`
+const LOWER_QUANTILE: f64 = 0.1;
+const UPPER_QUANTILE: f64 = 0.9;
+
+/// Historic junction to junction durations, collected from telegram runs.
+#[derive(Debug, Clone, Default)]
+pub struct TravelTimes {
+    by_line: HashMap<(Line, Junction, Junction), Vec<Duration>>,
+    // fallback for hops that were never observed on the requested line
+    any_line: HashMap<(Junction, Junction), Vec<Duration>>,
+}
+
+/// Expected duration of a single hop between two junctions.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct HopTime {
+    pub lower: Duration,
+    pub median: Duration,
+    pub upper: Duration,
+}
+
+/// Estimated time of arrival with its uncertainty interval.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Eta {
+    pub expected: SystemTime,
+    pub earliest: SystemTime,
+    pub latest: SystemTime,
+    /// predicted path including the current and the target junction
+    pub junctions: Vec<Junction>,
+}
+
+fn insert_sorted(durations: &mut Vec<Duration>, duration: Duration) {
+    let index = durations.partition_point(|d| *d < duration);
+    durations.insert(index, duration);
+}
+
+fn quantile(sorted: &[Duration], q: f64) -> Duration {
+    let index = ((sorted.len() - 1) as f64 * q).round() as usize;
+    sorted[index]
+}
+
+impl TravelTimes {
+    pub fn from_runs(runs: &[(LineRun, Vec<(SystemTime, Junction)>)]) -> Self {
+        let mut travel_times = TravelTimes::default();
+        for (line_run, junctions) in runs {
+            travel_times.add_run(&line_run.line, junctions);
+        }
+        travel_times
+    }
+
+    /// records the durations between all consecutive junctions of a run
+    pub fn add_run(&mut self, line: &Line, junctions: &[(SystemTime, Junction)]) {
+        for pair in junctions.windows(2) {
+            let ((start_time, start), (stop_time, stop)) = (pair[0], pair[1]);
+            if start == stop {
+                continue;
+            }
+            // out of order telegrams
+            let Ok(duration) = stop_time.duration_since(start_time) else {
+                continue;
+            };
+
+            insert_sorted(self.by_line.entry((line.clone(), start, stop)).or_default(), duration);
+            insert_sorted(self.any_line.entry((start, stop)).or_default(), duration);
+        }
+    }
+
+    pub fn hop(&self, line: &Line, start: Junction, stop: Junction) -> Option<HopTime> {
+        let samples = self.by_line.get(&(line.clone(), start, stop))
+            .or_else(|| self.any_line.get(&(start, stop)))?;
+
+        Some(HopTime {
+            lower: quantile(samples, LOWER_QUANTILE),
+            median: quantile(samples, 0.5),
+            upper: quantile(samples, UPPER_QUANTILE),
+        })
+    }
+}
+
+/// Predicts arrival times by walking the junction graph and summing up
+/// historic hop durations.
+#[derive(Debug, Clone)]
+pub struct ArrivalPredictor {
+    graph: RegionGraph,
+    travel_times: TravelTimes,
+    last_seen: HashMap<LineRun, (SystemTime, Junction)>,
+}
+
+impl ArrivalPredictor {
+    pub fn new(graph: RegionGraph, travel_times: TravelTimes) -> Self {
+        ArrivalPredictor {
+            graph,
+            travel_times,
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// feeds a live telegram, so the time already spent since the last
+    /// junction is taken into account
+    pub fn observe(&mut self, line_run: LineRun, time: SystemTime, junction: Junction) {
+        self.last_seen.insert(line_run, (time, junction));
+    }
+
+    /// fastest path by median duration, only using graph edges with
+    /// known travel times
+    pub fn route(&self, line: &Line, start: Junction, stop: Junction) -> Option<Vec<(Junction, HopTime)>> {
+        let mut best = HashMap::<Junction, Duration>::new();
+        let mut previous = HashMap::<Junction, (Junction, HopTime)>::new();
+        let mut queue = BinaryHeap::new();
+
+        best.insert(start, Duration::ZERO);
+        queue.push(Reverse((Duration::ZERO, start)));
+
+        while let Some(Reverse((duration, junction))) = queue.pop() {
+            if junction == stop {
+                break;
+            }
+            if best.get(&junction).is_some_and(|best| *best < duration) {
+                continue;
+            }
+
+            for next in self.graph.neighbours(&junction.0).into_iter().map(Junction) {
+                let Some(hop) = self.travel_times.hop(line, junction, next) else {
+                    continue;
+                };
+                let next_duration = duration + hop.median;
+                if best.get(&next).is_none_or(|best| next_duration < *best) {
+                    best.insert(next, next_duration);
+                    previous.insert(next, (junction, hop));
+                    queue.push(Reverse((next_duration, next)));
+                }
+            }
+        }
+
+        let mut path = vec![];
+        let mut junction = stop;
+        while junction != start {
+            let (last, hop) = previous.get(&junction)?;
+            path.push((junction, *hop));
+            junction = *last;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    pub fn predict_arrival(
+        &self,
+        line: &Line,
+        run: Run,
+        current_junction: Junction,
+        target_junction: Junction,
+        now: SystemTime,
+    ) -> Option<Eta> {
+        let path = self.route(line, current_junction, target_junction)?;
+
+        let mut elapsed = match self.last_seen.get(&LineRun { line: line.clone(), run }) {
+            Some((time, junction)) if *junction == current_junction => {
+                now.duration_since(*time).unwrap_or_default()
+            }
+            _ => Duration::ZERO,
+        };
+
+        let mut lower = Duration::ZERO;
+        let mut median = Duration::ZERO;
+        let mut upper = Duration::ZERO;
+        for (_, hop) in &path {
+            lower += hop.lower.saturating_sub(elapsed);
+            median += hop.median.saturating_sub(elapsed);
+            upper += hop.upper.saturating_sub(elapsed);
+            // only the first hop is already underway
+            elapsed = Duration::ZERO;
+        }
+
+        Some(Eta {
+            expected: now + median,
+            earliest: now + lower,
+            latest: now + upper,
+            junctions: std::iter::once(current_junction)
+                .chain(path.into_iter().map(|(junction, _)| junction))
+                .collect(),
+        })
+    }
+}
@@ -1,8 +1,8 @@
 use std::collections::{HashMap, HashSet};
 use std::time::{Duration, SystemTime};
 use geo::{prelude::{EuclideanDistance, GeodesicDistance, GeodesicLength}, LineString, Point, coord};
-use serde::{Serialize, Serializer, ser::{SerializeStruct, SerializeTuple}};
 use super::osm_lines::Waypoint;
+use super::output::{PlacedJunction, TrackSegment};
 use super::{Closest, ClosestPoint, Junction, LineRun};
 
 pub fn junctions_by_known_stops(
@@ -53,6 +53,9 @@ pub fn segment_run_by_known_stops(
                     })
                     .collect::<Vec<_>>();
                 by_known.insert((last_known, *junction), segment);
+            } else {
+                // the first segment starts at the known stop, too
+                next = vec![(*time, *junction)];
             }
             last_known = Some(*junction);
         }
@@ -75,7 +78,14 @@ pub fn to_rational(durations: &[(Duration, Junction)]) -> Vec<(f64, Junction)> {
         .collect()
 }
 
-pub fn way_point(ways: &[Vec<Waypoint>], known_point: &Point<f64>) -> Option<(usize, Point<f64>)> {
+/// default for the maximum distance in meters between a known stop and its way
+pub const MAX_WAY_DISTANCE: f64 = 30.0;
+
+pub fn way_point(
+    ways: &[Vec<Waypoint>],
+    known_point: &Point<f64>,
+    max_distance: f64,
+) -> Option<(usize, Point<f64>)> {
     let mut index = 0;
     ways.iter()
         .find_map(|way| {
@@ -106,13 +116,11 @@ pub fn way_point(ways: &[Vec<Waypoint>], known_point: &Point<f64>) -> Option<(us
                 })
                 .and_then(|(index, distance, closest_point)| {
                     // meters
-                    if distance < 30.0 {
+                    if distance < max_distance {
                         Some((index, closest_point))
                     } else {
                         None
                     }
-                }).map(|(index, closest_point)| {
-                    (index, closest_point)
                 })
         })
 }
@@ -122,7 +130,7 @@ fn split_linestring_at_point(linestring: LineString<f64>, point: &Point<f64>) ->
     let mut line_index = None;
     for (index, line) in linestring.lines().enumerate() {
         let dist = line.euclidean_distance(&point.0);
-        if min_dist.map_or(true, |min_dist| dist < min_dist) {
+        if min_dist.is_none_or(|min_dist| dist < min_dist) {
             min_dist = Some(dist);
             line_index = Some(index);
         }
@@ -131,7 +139,7 @@ fn split_linestring_at_point(linestring: LineString<f64>, point: &Point<f64>) ->
     if let Some(line_index) = line_index {
         let points = linestring.into_points();
         let (lines1, lines2) = points.split_at(line_index + 1);
-        return (LineString::new(lines1.iter().map(|p| p.0).chain([point.0].into_iter()).collect()),
+        return (LineString::new(lines1.iter().map(|p| p.0).chain([point.0]).collect()),
                 LineString::new([point.0].into_iter().chain(lines2.iter().map(|p| p.0)).collect()));
     }
 
@@ -150,84 +158,77 @@ pub struct Segment {
     pub start: (Junction, Point<f64>),
     pub stop: (Junction, Point<f64>),
     pub junctions: Vec<(f64, Junction)>,
+    /// expected duration from start to stop
+    pub duration: Duration,
 }
 
-#[derive(Clone, Debug)]
-pub enum ResultSegment {
-    Junction(Junction, Point<f64>),
-    Point(Point<f64>),
-}
-
-impl Serialize for ResultSegment {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self {
-            ResultSegment::Point(p) => {
-                let mut state = serializer.serialize_tuple(2)?;
-                state.serialize_element(&p.x())?;
-                state.serialize_element(&p.y())?;
-                state.end()
-            }
-            ResultSegment::Junction(junction, p) => {
-                let mut state = serializer.serialize_struct("Junction", 2)?;
-                state.serialize_field("junction", &junction.0)?;
-                state.serialize_field("pos", &[p.x(), p.y()])?;
-                state.end()
-            }
-        }
-    }
-}
-
-// segments must be ordered
+/// Places the junctions of a segment on the way between its known stops,
+/// the junctions must be ordered. Junctions beyond the end of the way are
+/// left out.
 pub fn segmentize(
     segment: &Segment,
     ways: &[Vec<Waypoint>],
-) -> Vec<Vec<ResultSegment>> {
+) -> Option<TrackSegment> {
     if segment.junctions.is_empty() {
-        return vec![];
+        return None;
     }
-    
-    ways.iter().filter_map(|way| {
-        let linestring = LineString::new(
+
+    // the way passing closest to both known stops
+    let linestring = ways.iter()
+        .map(|way| LineString::new(
             way.iter().map(|waypoint| coord! {
                 x: waypoint.lon,
                 y: waypoint.lat,
             }).collect()
-        );
-        let (_, linestring) = split_linestring_at_point(linestring, &segment.start.1);
-        let (linestring, _) = split_linestring_at_point(linestring, &segment.stop.1);
-        // return early if empty
-        linestring.lines().next()?;
-        let length = linestring_length(&linestring);
-
-        let mut results = vec![];
-        let mut distance = 0.0;
-        let mut junction_index = 0;
-        for line in linestring.lines() {
-            let new_distance = distance + line.geodesic_length();
-            while junction_index < segment.junctions.len() {
-                let junction = &segment.junctions[junction_index];
-                let junction_distance = junction.0 * length;
-                if new_distance > junction_distance {
-                    dbg!(junction_distance);
-                    let point = line.start_point() + (line.delta() * ((junction_distance - distance) / (new_distance - distance))).into();
-                    results.push(ResultSegment::Junction(junction.1, point));
-                    junction_index += 1;
+        ))
+        .filter(|linestring| linestring.lines().next().is_some())
+        .min_by(|l1, l2| {
+            let distance = |linestring: &LineString<f64>| segment.start.1.euclidean_distance(linestring)
+                + segment.stop.1.euclidean_distance(linestring);
+            distance(l1).total_cmp(&distance(l2))
+        })?;
+    let (_, linestring) = split_linestring_at_point(linestring, &segment.start.1);
+    let (linestring, _) = split_linestring_at_point(linestring, &segment.stop.1);
+    // return early if empty
+    linestring.lines().next()?;
+    let length = linestring_length(&linestring);
+    let duration = segment.duration.as_secs_f64();
+
+    let mut junctions = vec![];
+    let mut distance = 0.0;
+    let mut junction_index = 0;
+    for line in linestring.lines() {
+        let new_distance = distance + line.geodesic_length();
+        while junction_index < segment.junctions.len() {
+            let (ratio, junction) = segment.junctions[junction_index];
+            let junction_distance = ratio * length;
+            if new_distance >= junction_distance {
+                let point = if new_distance > distance {
+                    line.start_point() + (line.delta() * ((junction_distance - distance) / (new_distance - distance))).into()
                 } else {
-                    break
-                }
-
+                    line.start_point()
+                };
+                junctions.push(PlacedJunction {
+                    junction,
+                    lat: point.y(),
+                    lon: point.x(),
+                    along: junction_distance,
+                    time: ratio * duration,
+                });
+                junction_index += 1;
+            } else {
+                break
             }
-
-            results.push(ResultSegment::Point(line.end_point()));
-            distance = new_distance;
         }
+        distance = new_distance;
+    }
 
-        if junction_index != segment.junctions.len() {
-            println!("not all segments processed");
-        }
-        Some(results)
-    }).collect()
+    Some(TrackSegment {
+        start: segment.start.0,
+        stop: segment.stop.0,
+        geometry: linestring.points().map(|point| [point.x(), point.y()]).collect(),
+        length,
+        duration,
+        junctions,
+    })
 }
@@ -1,24 +1,34 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::time::{Duration, SystemTime};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use stop_names::LineReferences;
 use super::{Junction, Line, LineRun, Run};
 
-const RUN_MAX_GAP: Duration = Duration::from_secs(1800);
+/// default silence after which a line run is considered finished
+pub const RUN_MAX_GAP: Duration = Duration::from_secs(1800);
 
+/// Telegram as a csv row of the dump or a json message of the live feed.
 #[derive(Debug, Clone, Deserialize)]
-struct Telegram {
+struct CsvTelegram {
+    #[serde(alias = "time")]
     time_stamp: u64,
     // lat: f64,
     // lon: f64,
-    // station_id: u64,
-    line: Line,
-    // destination_number: u64,
+    #[serde(default)]
+    station_id: Option<String>,
+    #[serde(default)]
+    ip: Option<String>,
+    line: u16,
+    #[serde(default)]
+    destination_number: Option<u64>,
     // priority: (),
     // sign_of_deviation: (),
     // value_of_deviation: (),
     // reporting_point: (),
     // request_for_priority: (),
+    #[serde(default)]
+    direction_request: Option<u8>,
     run_number: Run,
     // reserve: (),
     // train_length: (),
@@ -26,46 +36,143 @@ struct Telegram {
     // junction_number: u16,
 }
 
-pub fn read_telegrams(path: &str) -> Result<Vec<(LineRun, Vec<(SystemTime, Junction)>)>, Box<dyn Error>> {
-    let mut amount = 0;
-    let mut errors = 0;
+/// A telegram of the dump. Direction, destination and receiver are only
+/// known if the dump has these columns.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Telegram {
+    pub time: SystemTime,
+    pub line: u16,
+    pub run: Run,
+    pub junction: Junction,
+    pub direction_request: Option<u8>,
+    pub destination_number: Option<u64>,
+    /// station id or ip of the receiver
+    pub receiver: Option<String>,
+}
+
+/// Telegram of a line run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunTelegram {
+    pub time: SystemTime,
+    pub junction: Junction,
+    pub direction_request: Option<u8>,
+    pub destination_number: Option<u64>,
+}
+
+impl From<CsvTelegram> for Telegram {
+    fn from(telegram: CsvTelegram) -> Self {
+        Telegram {
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(telegram.time_stamp),
+            line: telegram.line,
+            run: telegram.run_number,
+            junction: telegram.junction,
+            direction_request: telegram.direction_request,
+            destination_number: telegram.destination_number,
+            receiver: telegram.station_id.or(telegram.ip),
+        }
+    }
+}
+
+/// Parses a json telegram with the columns of the csv dump, `time` may be
+/// given instead of `time_stamp`.
+pub fn parse_json(json: &[u8]) -> Result<Telegram, serde_json::Error> {
+    serde_json::from_slice::<CsvTelegram>(json).map(Telegram::from)
+}
+
+/// Reads a telegram dump in csv format, with the errors of the broken rows.
+pub fn read_csv(path: &str) -> Result<(Vec<Telegram>, Vec<csv::Error>), Box<dyn Error>> {
+    let mut errors = vec![];
     let mut results = vec![];
-    let mut current = HashMap::<LineRun, Vec<(SystemTime, Junction)>>::new();
 
-    for result in csv::Reader::from_path(path)?.deserialize::<Telegram>() {
+    for result in csv::Reader::from_path(path)?.deserialize::<CsvTelegram>() {
         match result {
-            Err(e) => {
-                eprintln!("Parse error: {}", e);
-                errors += 1;
-            }
-            Ok(telegram) => {
-                let line_run = LineRun { line: telegram.line, run: telegram.run_number };
-                let time = SystemTime::UNIX_EPOCH + Duration::from_secs(telegram.time_stamp);
-                let junctions = current.entry(line_run)
-                    .or_default();
-                if Some(telegram.junction) != junctions.last().map(|(_time, junction)| *junction) {
-                    junctions.push((time, telegram.junction));
-                }
-                amount += 1;
-
-                current.retain(|line_run, junctions| {
-                    let last_update = junctions.last().unwrap().0;
-                    if last_update + RUN_MAX_GAP < time {
-                        results.push((*line_run, junctions.split_off(0)));
-                        false
-                    } else {
-                        true
-                    }
-                });
+            Err(e) => errors.push(e),
+            Ok(telegram) => results.push(Telegram::from(telegram)),
+        }
+    }
+
+    Ok((results, errors))
+}
+
+/// Splits telegrams into line runs, `lines` maps the R09 line numbers to the
+/// public references of the region. Repeated telegrams are dropped unless
+/// their direction or destination changed.
+pub fn group_runs(
+    telegrams: &[Telegram],
+    run_gap: Duration,
+    lines: &LineReferences,
+) -> Vec<(LineRun, Vec<RunTelegram>)> {
+    let mut results = vec![];
+    let mut current = HashMap::<LineRun, Vec<RunTelegram>>::new();
+
+    for telegram in telegrams {
+        let line_run = LineRun {
+            line: Line::from_number(telegram.line, lines),
+            run: telegram.run,
+        };
+        let time = telegram.time;
+        // finish stale runs before this telegram can extend them
+        current.retain(|line_run, telegrams| {
+            let last_update = telegrams.last().unwrap().time;
+            if last_update + run_gap < time {
+                results.push((line_run.clone(), telegrams.split_off(0)));
+                false
+            } else {
+                true
             }
+        });
+
+        let telegrams = current.entry(line_run)
+            .or_default();
+        let repeated = telegrams.last().is_some_and(|last| {
+            last.junction == telegram.junction &&
+            last.direction_request == telegram.direction_request &&
+            last.destination_number == telegram.destination_number
+        });
+        if !repeated {
+            telegrams.push(RunTelegram {
+                time,
+                junction: telegram.junction,
+                direction_request: telegram.direction_request,
+                destination_number: telegram.destination_number,
+            });
         }
     }
 
-    for (line_run, junctions) in current {
-        results.push((line_run, junctions));
+    for (line_run, telegrams) in current {
+        results.push((line_run, telegrams));
     }
 
-    println!("{}: parsed {} telegrams into {} line runs, {} errors", path, amount, results.len(), errors);
+    results
+}
+
+/// Line runs of a dump, broken rows are skipped.
+pub fn read_run_telegrams(
+    path: &str,
+    run_gap: Duration,
+    lines: &LineReferences,
+) -> Result<Vec<(LineRun, Vec<RunTelegram>)>, Box<dyn Error>> {
+    Ok(group_runs(&read_csv(path)?.0, run_gap, lines))
+}
+
+/// the junctions of a run without consecutive duplicates
+pub fn junctions(telegrams: &[RunTelegram]) -> Vec<(SystemTime, Junction)> {
+    let mut junctions: Vec<(SystemTime, Junction)> = vec![];
+    for telegram in telegrams {
+        if Some(telegram.junction) != junctions.last().map(|(_time, junction)| *junction) {
+            junctions.push((telegram.time, telegram.junction));
+        }
+    }
+    junctions
+}
 
-    Ok(results)
+pub fn read_telegrams(
+    path: &str,
+    run_gap: Duration,
+    lines: &LineReferences,
+) -> Result<Vec<(LineRun, Vec<(SystemTime, Junction)>)>, Box<dyn Error>> {
+    Ok(read_run_telegrams(path, run_gap, lines)?
+        .into_iter()
+        .map(|(line_run, telegrams)| (line_run, junctions(&telegrams)))
+        .collect())
 }
@@ -0,0 +1,645 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+use geo::Point;
+use stop_names::{Edge, LineReferences, PositionStatus, Receiver, ReceiverRegistry, RegionGraph, RegionMetaInformation};
+use crate::known_stops::Stop;
+use crate::prediction::{ArrivalPredictor, TravelTimes};
+use crate::output::{self, LineDocument};
+use crate::segments::{self, MAX_WAY_DISTANCE};
+use crate::osm_lines::{Extract, Id, Problem, RecordType, RelationFilter, StopKind};
+use crate::cleaning::{CleaningConfig, Rejections};
+use crate::trips::TripBoundary;
+use crate::{cleaning, consistency, coverage, gaps, map_matching, osm_lines, osm_pbf, pipeline, telegram, trips, Junction, Line, LineRun, Run};
+
+const TELEGRAMS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/telegrams.csv");
+const OVERPASS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/overpass.json");
+const OSM_XML: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/overpass.osm");
+const PTV2: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/ptv2.json");
+const OSM_PBF: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/overpass.osm.pbf");
+const CLEANING: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/cleaning.csv");
+const TRIPS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/trips.csv");
+
+fn at(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn run(line: u16, run: u16, junctions: &[(u64, u32)]) -> (LineRun, Vec<(SystemTime, Junction)>) {
+    (
+        LineRun { line: Line::new(line), run: Run(run) },
+        junctions.iter().map(|(secs, junction)| (at(*secs), Junction(*junction))).collect(),
+    )
+}
+
+#[test]
+fn test_predict_arrival() {
+    // 1 -> 2 -> 3 is faster than 1 -> 4 -> 3
+    let graph = RegionGraph::from(stop_names::HashMap::from([
+        (1, stop_names::HashMap::from([(0, 2), (1, 4)])),
+        (2, stop_names::HashMap::from([(0, 3)])),
+        (4, stop_names::HashMap::from([(0, 3)])),
+    ]));
+    let travel_times = TravelTimes::from_runs(&[
+        run(3, 1, &[(0, 1), (60, 2), (120, 3)]),
+        run(3, 2, &[(0, 1), (90, 2), (150, 3)]),
+        run(3, 3, &[(0, 1), (100, 4), (200, 3)]),
+    ]);
+    let mut predictor = ArrivalPredictor::new(graph, travel_times);
+
+    let eta = predictor.predict_arrival(&Line::new(3), Run(7), Junction(1), Junction(3), at(1000))
+        .expect("no eta");
+    assert_eq!(eta.junctions, vec![Junction(1), Junction(2), Junction(3)]);
+    assert_eq!(eta.earliest, at(1120));
+    assert_eq!(eta.latest, at(1150));
+
+    // the run has already been underway for 30s
+    predictor.observe(LineRun { line: Line::new(3), run: Run(7) }, at(970), Junction(1));
+    let eta = predictor.predict_arrival(&Line::new(3), Run(7), Junction(1), Junction(3), at(1000))
+        .expect("no eta");
+    assert_eq!(eta.earliest, at(1090));
+
+    assert!(predictor.predict_arrival(&Line::new(3), Run(7), Junction(3), Junction(1), at(1000)).is_none());
+}
+
+fn fixture_stops() -> HashMap<Junction, Stop> {
+    HashMap::from([
+        (Junction(100), Stop { name: "Alpha".to_string(), lat: 51.0501, lon: 13.701 }),
+        (Junction(200), Stop { name: "Omega".to_string(), lat: 51.0501, lon: 13.739 }),
+        // far away from the fixture line
+        (Junction(300), Stop { name: "Elsewhere".to_string(), lat: 51.06, lon: 13.72 }),
+    ])
+}
+
+fn junctions(run: &[(SystemTime, Junction)]) -> Vec<u32> {
+    run.iter().map(|(_, junction)| junction.0).collect()
+}
+
+#[test]
+fn test_is_similar_sequence() {
+    let goal = [Junction(1), Junction(2), Junction(3), Junction(4)];
+    assert!(pipeline::is_similar_sequence(&[Junction(1), Junction(3)], &goal));
+    assert!(!pipeline::is_similar_sequence(&[Junction(3), Junction(1)], &goal));
+    assert!(!pipeline::is_similar_sequence(&[Junction(1), Junction(5)], &goal));
+}
+
+#[test]
+fn test_read_telegrams_splits_runs() {
+    let mut runs = telegram::read_telegrams(TELEGRAMS, telegram::RUN_MAX_GAP, &LineReferences::new())
+        .expect("cannot read telegrams");
+    runs.sort_by_key(|(line_run, junctions)| (line_run.clone(), junctions[0].0));
+
+    assert_eq!(runs.len(), 3);
+    assert_eq!(runs[0].0, LineRun { line: Line::new(3), run: Run(1) });
+    // consecutive duplicates are dropped
+    assert_eq!(junctions(&runs[0].1), vec![100, 150, 160, 200]);
+    // the second trip starts after more than RUN_MAX_GAP of silence
+    assert_eq!(junctions(&runs[1].1), vec![200, 160]);
+    assert_eq!(runs[2].0, LineRun { line: Line::new(7), run: Run(2) });
+
+    // the broken row is returned instead of printed
+    let (telegrams, errors) = telegram::read_csv(TELEGRAMS).expect("cannot read telegrams");
+    assert_eq!((telegrams.len(), errors.len()), (9, 1));
+
+    // without the gap both trips are one run
+    let runs = telegram::read_telegrams(TELEGRAMS, Duration::from_secs(86400), &LineReferences::new())
+        .expect("cannot read telegrams");
+    assert_eq!(runs.len(), 2);
+
+    // the public reference of the region is attached to the R09 line number
+    let lines = LineReferences::from([(7, "E7".to_string())]);
+    let runs = telegram::read_telegrams(TELEGRAMS, telegram::RUN_MAX_GAP, &lines)
+        .expect("cannot read telegrams");
+    let line = runs.iter()
+        .map(|(line_run, _)| &line_run.line)
+        .find(|line| line.number == 7)
+        .expect("line 7 missing");
+    assert_eq!(line.to_string(), "E7");
+}
+
+fn line_graph() -> RegionGraph {
+    RegionGraph::from(stop_names::HashMap::from([
+        (100, stop_names::HashMap::from([(1, 150)])),
+        (150, stop_names::HashMap::from([(1, 160)])),
+        (160, stop_names::HashMap::from([(1, 200)])),
+    ]))
+}
+
+#[test]
+fn test_clean_telegrams() {
+    let telegrams = telegram::read_csv(CLEANING).expect("cannot read telegrams").0;
+    let graph = line_graph();
+    let (telegrams, rejections) = cleaning::clean(telegrams, &CleaningConfig::default(), Some(&graph), &fixture_stops());
+
+    assert_eq!(rejections, Rejections { out_of_order: 1, duplicate: 1, unreachable: 1, impossible_speed: 1 });
+    let kept = |line: u16| telegrams.iter()
+        .filter(|telegram| telegram.line == line)
+        .map(|telegram| telegram.junction.0)
+        .collect::<Vec<_>>();
+    assert_eq!(kept(3), [100, 150, 160, 200]);
+    // 2.7km in 10 seconds
+    assert_eq!(kept(7), [100]);
+    assert_eq!(telegrams[0].receiver.as_deref(), Some("10.13.37.100"));
+
+    // a bit error with a known position is accepted first, the vehicle is
+    // re-anchored on the real junctions after max_unreachable of them
+    let vehicle = [(0, 300), (12, 100), (24, 100), (36, 100), (300, 200)].map(|(secs, junction)| telegram::Telegram {
+        time: at(secs),
+        line: 9,
+        run: Run(1),
+        junction: Junction(junction),
+        direction_request: None,
+        destination_number: None,
+        receiver: None,
+    });
+    let (telegrams, rejections) = cleaning::clean(vehicle.to_vec(), &CleaningConfig::default(), None, &fixture_stops());
+    assert_eq!(rejections, Rejections { impossible_speed: 2, ..Default::default() });
+    assert_eq!(telegrams.iter().map(|telegram| telegram.junction.0).collect::<Vec<_>>(), [300, 100, 200]);
+
+    assert!(cleaning::is_reachable(&graph, Junction(100), Junction(200), 3));
+    assert!(!cleaning::is_reachable(&graph, Junction(100), Junction(200), 2));
+
+    // a new vehicle every minute, the live filter only keeps the ones seen
+    // within max_gap and the receptions within the dedup window
+    let mut filter = cleaning::OutlierFilter::new(CleaningConfig::default());
+    let mut rejections = Rejections::default();
+    for i in 0..100 {
+        let telegram = telegram::Telegram {
+            time: at(60 * i),
+            line: 3,
+            run: Run(i as u16),
+            junction: Junction(100),
+            direction_request: None,
+            destination_number: None,
+            receiver: None,
+        };
+        assert!(filter.accept(&telegram, Some(&graph), &HashMap::new(), &mut rejections));
+    }
+    assert_eq!(filter.remembered(), 11 + 1);
+}
+
+#[test]
+fn test_fill_gaps() {
+    let graph = line_graph();
+    let inferred = |passages: &[gaps::Passage]| passages.iter()
+        .map(|passage| (passage.time, passage.junction.0, passage.inferred))
+        .collect::<Vec<_>>();
+
+    // 150 and 160 were missed, the single successor graph has no travel times
+    let passages = gaps::fill_gaps(&[(at(0), Junction(100)), (at(180), Junction(200))], &graph, Some(3), gaps::MAX_SKIPPED);
+    assert_eq!(inferred(&passages), [
+        (at(0), 100, false),
+        (at(60), 150, true),
+        (at(120), 160, true),
+        (at(180), 200, false),
+    ]);
+    assert_eq!(gaps::timeline(&passages).len(), 4);
+    // too many junctions missing, or none at all
+    assert_eq!(gaps::fill_gaps(&[(at(0), Junction(100)), (at(180), Junction(200))], &graph, None, 1).len(), 2);
+    assert_eq!(gaps::fill_gaps(&[(at(0), Junction(100)), (at(60), Junction(150))], &graph, None, 1).len(), 2);
+
+    // timestamps follow the median travel times of the line
+    let edge = |target: u32, median_travel_time: f64| Edge {
+        target,
+        count: 4,
+        median_travel_time: Some(median_travel_time),
+        lines: vec![3],
+    };
+    let graph = RegionGraph {
+        structure: stop_names::HashMap::from([
+            (1, stop_names::HashMap::from([(0, vec![edge(2, 10.0)])])),
+            (2, stop_names::HashMap::from([(0, vec![edge(3, 30.0)])])),
+        ]),
+    };
+    let passages = gaps::fill_gaps(&[(at(0), Junction(1)), (at(80), Junction(3))], &graph, Some(3), gaps::MAX_SKIPPED);
+    assert_eq!(inferred(&passages)[1], (at(20), 2, true));
+
+    // negative and non finite medians count as unknown travel times
+    for median_travel_time in [-50.0, f64::NAN, f64::INFINITY] {
+        let graph = RegionGraph {
+            structure: stop_names::HashMap::from([
+                (1, stop_names::HashMap::from([(0, vec![edge(2, median_travel_time)])])),
+                (2, stop_names::HashMap::from([(0, vec![edge(3, 60.0)])])),
+            ]),
+        };
+        let passages = gaps::fill_gaps(&[(at(0), Junction(1)), (at(120), Junction(3))], &graph, Some(3), gaps::MAX_SKIPPED);
+        assert_eq!(inferred(&passages)[1], (at(60), 2, true));
+    }
+}
+
+#[test]
+fn test_graph_consistency() {
+    let runs = telegram::read_run_telegrams(TRIPS, telegram::RUN_MAX_GAP, &LineReferences::new())
+        .expect("cannot read telegrams");
+    let mut graph = line_graph();
+    // 2.7km in 10 seconds
+    graph.structure.entry(100).or_default().insert(2, vec![Edge {
+        target: 200,
+        count: 1,
+        median_travel_time: Some(10.0),
+        lines: vec![],
+    }]);
+
+    // 150 is in stops.json, but without coordinates
+    let unlocated = HashSet::from([Junction(150)]);
+    let report = consistency::check(&graph, &runs, &fixture_stops(), &unlocated, 30.0, Duration::from_secs(600));
+    assert_eq!(report.unobserved_edges, [consistency::GraphEdge { from: Junction(100), direction: 2, to: Junction(200) }]);
+    let missing = report.missing_edges.iter()
+        .map(|transition| (transition.from.0, transition.to.0))
+        .collect::<Vec<_>>();
+    assert_eq!(missing, [(160, 150), (200, 260), (260, 270), (270, 280)]);
+    assert_eq!(report.missing_edges[1].directions, [2]);
+    assert_eq!(report.unknown_junctions, [Junction(160)]);
+    assert_eq!(report.unlocated_junctions, [Junction(150)]);
+    assert_eq!(report.implausible_edges.len(), 1);
+    assert_eq!((report.implausible_edges[0].from, report.implausible_edges[0].travel_time), (Junction(100), 10.0));
+    assert!(!report.is_empty());
+}
+
+#[cfg(feature = "live")]
+#[test]
+fn test_live_ingest() {
+    use crate::ingest::{self, Pipeline, Source};
+    use crate::tracker::LiveTelegram;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("cannot start runtime");
+    let (received, pipeline) = runtime.block_on(async {
+        let pipeline = Pipeline::new(fixture_stops(), Some(line_graph()), CleaningConfig::default());
+        let source = Source::Replay { path: CLEANING.to_string(), speed: 0.0 };
+        let (sink, mut receiver) = tokio::sync::mpsc::channel(ingest::CHANNEL_CAPACITY);
+        let ingest = tokio::spawn(ingest::ingest(source, pipeline, sink));
+        let mut received: Vec<LiveTelegram> = vec![];
+        while let Some(live) = receiver.recv().await {
+            received.push(live);
+        }
+        (received, ingest.await.expect("ingest panicked").expect("ingest failed"))
+    });
+
+    // the replay is ordered by time, so the late 160 is kept
+    let junctions = received.iter().map(|live| (live.telegram.line, live.telegram.junction.0)).collect::<Vec<_>>();
+    assert_eq!(junctions, [(3, 100), (3, 160), (3, 160), (3, 200), (7, 100)]);
+    assert_eq!(pipeline.rejections, Rejections { out_of_order: 0, duplicate: 1, unreachable: 2, impossible_speed: 1 });
+    assert_eq!(received[0].stop.as_ref().map(|stop| stop.name.as_str()), Some("Alpha"));
+    assert_eq!(received[1].stop, None);
+
+    let vehicle = pipeline.tracker.get(3, Run(1)).expect("vehicle missing");
+    assert_eq!((vehicle.junction, vehicle.previous), (Junction(200), Some(Junction(160))));
+    assert_eq!(vehicle.stop.as_ref().map(|stop| stop.name.as_str()), Some("Omega"));
+    let mut tracker = pipeline.tracker.clone();
+    assert_eq!(tracker.expire(at(1500), Duration::from_secs(600)).len(), 0);
+    assert_eq!(tracker.expire(at(2500), Duration::from_secs(600)).len(), 1);
+
+    // json telegrams over udp, other messages are passed on as errors
+    let telegram = runtime.block_on(async {
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.expect("cannot bind");
+        let address = socket.local_addr().expect("no address");
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(ingest::CHANNEL_CAPACITY);
+        tokio::spawn(ingest::udp(socket, sender));
+
+        let client = tokio::net::UdpSocket::bind("127.0.0.1:0").await.expect("cannot bind");
+        client.send_to(b"not a telegram", address).await.expect("cannot send");
+        client.send_to(br#"{"time": 1000, "line": 3, "run_number": 1, "junction": 100, "station_id": "alpha"}"#, address)
+            .await
+            .expect("cannot send");
+        assert!(receiver.recv().await.expect("no message").is_err());
+        receiver.recv().await
+    });
+    let telegram = telegram.expect("no telegram").expect("not a telegram");
+    assert_eq!((telegram.time, telegram.junction, telegram.receiver.as_deref()), (at(1000), Junction(100), Some("alpha")));
+}
+
+#[test]
+fn test_receiver_coverage() {
+    let telegrams = telegram::read_csv(CLEANING).expect("cannot read telegrams").0;
+    let registry = ReceiverRegistry {
+        receivers: vec![Receiver {
+            id: 7,
+            name: Some("Alpha".to_string()),
+            ip: Some("10.13.37.100".to_string()),
+            station_id: None,
+            region: Some(0),
+            lat: Some(51.05),
+            lon: Some(13.7),
+        }],
+    };
+
+    let coverage = coverage::coverage(&telegrams, &registry, coverage::PASSAGE_WINDOW);
+    assert_eq!(coverage.len(), 2);
+    assert_eq!((coverage[0].receiver.as_str(), coverage[0].id, coverage[0].telegrams), ("10.13.37.100", Some(7), 7));
+    assert_eq!(coverage[1].id, None);
+    // both receivers got the first passage at 100, only .100 the second one
+    let junction_100 = |receiver: &coverage::ReceiverCoverage| receiver.junctions.iter()
+        .find(|junction| junction.junction == Junction(100))
+        .cloned();
+    assert_eq!(junction_100(&coverage[0]).map(|junction| (junction.passages, junction.reliability)), Some((2, 1.0)));
+    assert_eq!(junction_100(&coverage[1]).map(|junction| (junction.passages, junction.reliability)), Some((1, 0.5)));
+
+    let heatmap = coverage::heatmap(&coverage, &registry, &fixture_stops());
+    let features = heatmap["features"].as_array().expect("no features");
+    // the receiver itself and its junctions with known positions
+    assert_eq!(features[0]["properties"]["receiver"], 7);
+    assert_eq!(features.iter().filter(|feature| feature["properties"]["junction"] == 100).count(), 2);
+}
+
+#[test]
+fn test_split_trips() {
+    let mut runs = telegram::read_run_telegrams(TRIPS, telegram::RUN_MAX_GAP, &LineReferences::new())
+        .expect("cannot read telegrams");
+    runs.sort_by_key(|(line_run, _)| line_run.clone());
+    let graph = line_graph();
+    let split = |graph: Option<&RegionGraph>| trips::split_runs(&runs, graph)
+        .into_iter()
+        .map(|trip| (trip.line_run.run.0, trip.boundary, junctions(&trip.junctions())))
+        .collect::<Vec<_>>();
+
+    assert_eq!(split(Some(&graph)), vec![
+        (1, TripBoundary::RunStart, vec![100, 150, 160, 200]),
+        (1, TripBoundary::DirectionChange, vec![200, 260]),
+        (2, TripBoundary::RunStart, vec![100, 150, 160]),
+        (2, TripBoundary::Turnaround, vec![150]),
+        (3, TripBoundary::RunStart, vec![260, 270]),
+        (3, TripBoundary::DestinationChange, vec![280]),
+    ]);
+    // without the graph going back is only noticed by the repeated junction
+    assert_eq!(split(None)[3], (2, TripBoundary::RepeatedJunction, vec![150]));
+
+    let mut trips = trips::split_runs(&runs, Some(&graph));
+    let line_infos = osm_lines::read(OVERPASS).expect("cannot read overpass json");
+    trips::label_trips(&mut trips, &line_infos, &fixture_stops(), MAX_WAY_DISTANCE);
+    let variants = trips.iter()
+        .map(|trip| trip.variant.as_ref().map(|variant| variant.relation))
+        .collect::<Vec<_>>();
+    assert_eq!(variants, [Some(Id(30)), None, None, None, None, None]);
+}
+
+#[test]
+fn test_find_known_stops() {
+    let lines = osm_lines::read(OVERPASS).expect("cannot read overpass json");
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0].line, Some(Line::new(3)));
+
+    let known_stops = pipeline::find_known_stops(&fixture_stops(), &lines[0], MAX_WAY_DISTANCE);
+    let known_junctions = known_stops.iter()
+        .map(|(_, junction, _)| junction.0)
+        .collect::<Vec<_>>();
+    assert_eq!(known_junctions, vec![100, 200]);
+}
+
+#[test]
+fn test_run_segments() {
+    let stops = fixture_stops();
+    let known_stops = stops.keys().copied().collect::<HashSet<_>>();
+    let runs = segments::junctions_by_known_stops(
+        &known_stops,
+        telegram::read_telegrams(TELEGRAMS, telegram::RUN_MAX_GAP, &LineReferences::new()).expect("cannot read telegrams"),
+    );
+
+    let matching = pipeline::matching_runs(Line::new(3), &[Junction(100), Junction(200)], &runs);
+    assert_eq!(matching.len(), 1);
+
+    let run_segments = pipeline::run_segments(&known_stops, &matching);
+    let longest = pipeline::longest_segments(&run_segments);
+    assert_eq!(longest[&(Junction(100), Junction(200))], vec![
+        (Duration::ZERO, Junction(100)),
+        (Duration::from_secs(60), Junction(150)),
+        (Duration::from_secs(30), Junction(160)),
+        (Duration::from_secs(30), Junction(200)),
+    ]);
+
+    let min_durations = pipeline::min_durations(&[
+        ((Junction(100), Junction(200)), vec![(Duration::ZERO, Junction(100)), (Duration::from_secs(50), Junction(200))]),
+        ((Junction(100), Junction(200)), vec![(Duration::ZERO, Junction(100)), (Duration::from_secs(40), Junction(200))]),
+    ]);
+    assert_eq!(min_durations[&(Junction(100), Junction(200))], Duration::from_secs(40));
+}
+
+#[test]
+fn test_segmentize() {
+    let stops = fixture_stops();
+    let known_stops = stops.keys().copied().collect::<HashSet<_>>();
+    let runs = segments::junctions_by_known_stops(
+        &known_stops,
+        telegram::read_telegrams(TELEGRAMS, telegram::RUN_MAX_GAP, &LineReferences::new()).expect("cannot read telegrams"),
+    );
+    let line_info = osm_lines::read(OVERPASS).expect("cannot read overpass json")
+        .remove(0);
+
+    let (result, report) = pipeline::analyze_line(line_info, &stops, &runs, MAX_WAY_DISTANCE);
+    let result = result.expect("no result");
+    assert_eq!((report.known_stops, report.matching_runs, report.new_junctions, report.missing_durations, report.unplaced_junctions), (2, 1, 2, 0, 0));
+    assert_eq!(result.relation, Id(30));
+    assert_eq!(result.stops.iter().map(|stop| stop.name.as_str()).collect::<Vec<_>>(), ["Alpha", "Omega"]);
+    assert_eq!(result.segments.len(), 1);
+    let segment = &result.segments[0];
+    assert_eq!((segment.start, segment.stop), (Junction(100), Junction(200)));
+    assert_eq!(segment.duration, 120.0);
+    assert_eq!(segment.geometry.first().map(|point| point[0]), Some(13.701));
+
+    // 150 is reached after half of the time between 100 and 200, 160 after 3/4
+    let placed = &segment.junctions;
+    assert_eq!(placed.iter().map(|placed| placed.junction.0).collect::<Vec<_>>(), [100, 150, 160, 200]);
+    assert!((placed[0].lon - 13.701).abs() < 1e-6);
+    assert!((placed[1].lon - 13.720).abs() < 1e-4);
+    assert!((placed[2].lon - 13.7295).abs() < 1e-4);
+    assert!((placed[1].along - segment.length / 2.0).abs() < 1e-6);
+    assert_eq!(placed[2].time, 90.0);
+
+    let line_document = LineDocument::new(Line::new(3), vec![result.clone()]);
+    assert_eq!(line_document.document.schema_version, output::SCHEMA_VERSION);
+    let geojson = line_document.geojson();
+    // one LineString and four Points
+    assert_eq!(geojson["features"].as_array().map(Vec::len), Some(5));
+    assert_eq!(geojson["features"][2]["properties"]["junction"], 150);
+
+    // the placements of the new junctions become a patch for stops.json
+    let samples = map_matching::variant_samples(&result, &known_stops);
+    let estimates = map_matching::estimate_positions(&samples);
+    assert_eq!(estimates.iter().map(|estimate| estimate.junction.0).collect::<Vec<_>>(), [150, 160]);
+    assert!((estimates[1].position.lon.unwrap() - 13.7295).abs() < 1e-4);
+    assert!((estimates[1].error - 1330.0).abs() < 10.0);
+    assert_eq!(estimates[1].position.status, PositionStatus::EstimatedFromOsm);
+    assert_eq!(estimates[1].position.accuracy, Some(estimates[1].error));
+    let meta = RegionMetaInformation {
+        city_name: Some("Dresden".to_string()),
+        ..Default::default()
+    };
+    let patch = map_matching::proposed_patch(0, &meta, &estimates);
+    assert_eq!(patch.data[&0][&160], vec![estimates[1].position.clone()]);
+    let region = patch.extract(&0).expect("patch without region");
+    assert_eq!(region.meta, meta);
+}
+
+#[test]
+fn test_map_matching() {
+    let stops = fixture_stops();
+    let known_stops = stops.keys().copied().collect::<HashSet<_>>();
+    let runs = segments::junctions_by_known_stops(
+        &known_stops,
+        telegram::read_telegrams(TELEGRAMS, telegram::RUN_MAX_GAP, &LineReferences::new()).expect("cannot read telegrams"),
+    );
+    let line_info = osm_lines::read(OVERPASS).expect("cannot read overpass json")
+        .remove(0);
+
+    let samples = map_matching::match_line(&line_info, &stops, &runs, MAX_WAY_DISTANCE);
+    let estimates = map_matching::estimate_positions(&samples);
+    assert_eq!(estimates.iter().map(|estimate| estimate.junction.0).collect::<Vec<_>>(), [150, 160]);
+    assert!((estimates[0].position.lon.unwrap() - 13.720).abs() < 1e-4);
+    assert!((estimates[1].position.lon.unwrap() - 13.7295).abs() < 1e-4);
+    // a single run only narrows it down to the segment between Alpha and Omega
+    assert_eq!(estimates[0].samples, 1);
+    assert!((estimates[0].error - 1330.0).abs() < 10.0);
+
+    // two agreeing samples and an outlier 100m further along
+    let sample = |lon: f64| map_matching::Sample { point: Point::new(lon, 51.0501), segment_length: 2000.0 };
+    let estimate = map_matching::estimate(Junction(150), &[sample(13.72), sample(13.72), sample(13.7214)])
+        .expect("no estimate");
+    assert_eq!(estimate.position.lon, Some(13.72));
+    assert!((estimate.error - 100.0 / 3f64.sqrt()).abs() < 2.0);
+}
+
+fn line_refs(path: &str, filter: &RelationFilter) -> Vec<String> {
+    let mut lines = osm_lines::read_filtered(path, filter)
+        .expect("cannot read osm extract")
+        .into_iter()
+        .map(|line_info| line_info.reference)
+        .collect::<Vec<_>>();
+    lines.sort_unstable();
+    lines
+}
+
+#[test]
+fn test_read_osm_extracts() {
+    let overpass = osm_lines::read(OVERPASS).expect("cannot read overpass json").remove(0);
+    for path in [OSM_XML, OSM_PBF] {
+        let lines = osm_lines::read(path).expect("cannot read osm extract");
+        let tram = lines.iter()
+            .find(|line_info| line_info.line == Some(Line::new(3)))
+            .expect("line 3 missing");
+        assert_eq!(tram.name, overpass.name);
+        assert_eq!(tram.ways, overpass.ways);
+        for (waypoint, expected) in tram.ways[0].iter().zip(&overpass.ways[0]) {
+            assert!((waypoint.lat - expected.lat).abs() < 1e-7);
+            assert!((waypoint.lon - expected.lon).abs() < 1e-7);
+        }
+
+        assert_eq!(line_refs(path, &RelationFilter::default()), ["3", "61"]);
+        // route_master 40 selects its member route 30
+        assert_eq!(line_refs(path, &RelationFilter { relations: vec![40], ..Default::default() }), ["3"]);
+        assert_eq!(line_refs(path, &RelationFilter { networks: vec!["Other".to_string()], ..Default::default() }), ["61"]);
+    }
+
+    // JOSM saves objects that were never uploaded with negative ids
+    let josm = std::env::temp_dir().join(format!("runalyzer-josm-{}.osm", std::process::id()));
+    let xml = std::fs::read_to_string(OSM_XML).expect("cannot read osm extract")
+        .replace("id=\"", "id=\"-")
+        .replace("ref=\"", "ref=\"-");
+    std::fs::write(&josm, xml).expect("cannot write osm extract");
+    let lines = osm_lines::read(josm.to_str().unwrap()).expect("cannot read osm extract");
+    std::fs::remove_file(&josm).ok();
+    let tram = lines.iter()
+        .find(|line_info| line_info.line == Some(Line::new(3)))
+        .expect("line 3 missing");
+    assert_eq!(tram.relation, Id(-30));
+    assert_eq!(tram.ways[0].iter().map(|waypoint| -waypoint.id.0).collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+}
+
+/// a protobuf varint
+fn varint(mut value: u64) -> Vec<u8> {
+    let mut bytes = vec![];
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+    bytes
+}
+
+/// a length delimited protobuf field
+fn field(number: u64, value: &[u8]) -> Vec<u8> {
+    [varint(number << 3 | 2), varint(value.len() as u64), value.to_vec()].concat()
+}
+
+/// a PBF file of a single uncompressed OSMData blob
+fn pbf(block: &[u8]) -> Vec<u8> {
+    let blob = field(1, block);
+    let header = [field(1, b"OSMData"), varint(3 << 3), varint(blob.len() as u64)].concat();
+    [(header.len() as u32).to_be_bytes().to_vec(), header, blob].concat()
+}
+
+#[test]
+fn test_crafted_pbf() {
+    let rejected = |content: &[u8]| {
+        let path = std::env::temp_dir().join(format!("runalyzer-crafted-{}.osm.pbf", std::process::id()));
+        std::fs::write(&path, content).expect("cannot write osm extract");
+        let result = osm_lines::read(path.to_str().unwrap());
+        std::fs::remove_file(&path).ok();
+        result.is_err()
+    };
+    // sizes beyond the limits of the format
+    assert!(rejected(&u32::MAX.to_be_bytes()));
+    let header = [field(1, b"OSMData"), varint(3 << 3), varint(1 << 40)].concat();
+    assert!(rejected(&[(header.len() as u32).to_be_bytes().to_vec(), header].concat()));
+    // a field longer than the block
+    assert!(rejected(&pbf(&[varint(2 << 3 | 2), varint(u64::MAX)].concat())));
+
+    let dense_nodes = |lats: &[u64]| {
+        let packed = |values: &[u64]| values.iter().flat_map(|value| varint(*value)).collect::<Vec<_>>();
+        let nodes = [
+            field(1, &packed(&vec![2; lats.len()])),
+            field(8, &packed(lats)),
+            field(9, &packed(&vec![0; lats.len()])),
+        ].concat();
+        pbf(&field(2, &field(2, &nodes)))
+    };
+    // the zigzag encoded lat delta 1 gives a node at 1e-7 degrees
+    assert!(!rejected(&dense_nodes(&[2])));
+    // deltas that sum up beyond i64
+    assert!(rejected(&dense_nodes(&[u64::MAX - 1, u64::MAX - 1])));
+    // a coordinate beyond i64 after applying the granularity
+    assert!(rejected(&dense_nodes(&[u64::MAX - 1])));
+
+    // the relation pass decodes every block and remembers what they hold,
+    // a node pass for ids outside of them decodes none
+    let mut extract = osm_pbf::PbfFile::open(OSM_PBF).expect("cannot read osm extract");
+    let mut relations = 0;
+    extract.for_each_record(RecordType::Relation, None, &mut |_| relations += 1).expect("cannot read osm extract");
+    assert_eq!(relations, 3);
+    let mut nodes = 0;
+    extract.for_each_record(RecordType::Node, Some(&BTreeSet::from([Id(1000)])), &mut |_| nodes += 1).expect("cannot read osm extract");
+    assert_eq!(nodes, 0);
+    extract.for_each_record(RecordType::Node, Some(&BTreeSet::from([Id(21)])), &mut |_| nodes += 1).expect("cannot read osm extract");
+    assert_eq!(nodes, 9);
+}
+
+#[test]
+fn test_ptv2_route_assembly() {
+    let (lines, reports) = osm_lines::read_with_reports(PTV2, &RelationFilter::default())
+        .expect("cannot read overpass json");
+
+    assert_eq!(lines.len(), 1);
+    let line_info = &lines[0];
+    // the ref is inherited from the route_master
+    assert_eq!(line_info.reference, "EV3");
+    assert_eq!(line_info.line, None);
+    assert_eq!(line_info.route_master, Some(Id(41)));
+    assert_eq!(
+        line_info.stops.iter().map(|stop| stop.kind).collect::<Vec<_>>(),
+        vec![StopKind::StopPosition, StopKind::Platform, StopKind::StopPosition],
+    );
+    // way 11 is reversed, the roundabout is left at node 5
+    assert_eq!(line_info.ways.len(), 1);
+    assert_eq!(
+        line_info.ways[0].iter().map(|waypoint| waypoint.id.0).collect::<Vec<_>>(),
+        vec![1, 2, 3, 6, 5, 7],
+    );
+
+    assert_eq!(reports.len(), 2);
+    assert_eq!(reports[0].relation, Id(32));
+    assert_eq!(reports[0].problems, vec![
+        Problem::UnknownRole { member: Id(7), role: "via".to_string() },
+        Problem::MissingMember(RecordType::Way, Id(99)),
+    ]);
+    assert_eq!(reports[1].relation, Id(33));
+    assert_eq!(reports[1].problems, vec![Problem::MissingRef]);
+}
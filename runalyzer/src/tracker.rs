@@ -0,0 +1,81 @@
+//! Latest state of every vehicle in a live telegram stream.
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use serde::Serialize;
+use super::known_stops::Stop;
+use super::telegram::Telegram;
+use super::{Junction, Run};
+
+/// A telegram enriched with the known stop of its junction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LiveTelegram {
+    #[serde(flatten)]
+    pub telegram: Telegram,
+    pub stop: Option<Stop>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Vehicle {
+    pub line: u16,
+    pub run: Run,
+    pub junction: Junction,
+    /// junction reported before the current one
+    pub previous: Option<Junction>,
+    pub time: SystemTime,
+    pub direction_request: Option<u8>,
+    pub destination_number: Option<u64>,
+    /// last known stop the vehicle passed
+    pub stop: Option<Stop>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VehicleTracker {
+    vehicles: HashMap<(u16, Run), Vehicle>,
+}
+
+impl VehicleTracker {
+    /// Moves the vehicle of the telegram, the position is kept when the
+    /// junction has no known stop.
+    pub fn update(&mut self, live: &LiveTelegram) -> &Vehicle {
+        let telegram = &live.telegram;
+        let vehicle = self.vehicles.entry((telegram.line, telegram.run))
+            .or_insert_with(|| Vehicle {
+                line: telegram.line,
+                run: telegram.run,
+                junction: telegram.junction,
+                previous: None,
+                time: telegram.time,
+                direction_request: None,
+                destination_number: None,
+                stop: None,
+            });
+
+        if vehicle.junction != telegram.junction {
+            vehicle.previous = Some(vehicle.junction);
+            vehicle.junction = telegram.junction;
+        }
+        vehicle.time = vehicle.time.max(telegram.time);
+        vehicle.direction_request = telegram.direction_request.or(vehicle.direction_request);
+        vehicle.destination_number = telegram.destination_number.or(vehicle.destination_number);
+        if live.stop.is_some() {
+            vehicle.stop = live.stop.clone();
+        }
+        vehicle
+    }
+
+    pub fn get(&self, line: u16, run: Run) -> Option<&Vehicle> {
+        self.vehicles.get(&(line, run))
+    }
+
+    pub fn vehicles(&self) -> impl Iterator<Item = &Vehicle> {
+        self.vehicles.values()
+    }
+
+    /// Forgets the vehicles silent for longer than `max_age` and returns them.
+    pub fn expire(&mut self, now: SystemTime, max_age: Duration) -> Vec<Vehicle> {
+        let (expired, active): (HashMap<_, _>, HashMap<_, _>) = self.vehicles.drain()
+            .partition(|(_, vehicle)| vehicle.time + max_age < now);
+        self.vehicles = active;
+        expired.into_values().collect()
+    }
+}
@@ -0,0 +1,170 @@
+//! Splits line runs into directional trips. A vehicle keeps its run number
+//! when it turns around at the terminus, so one run usually covers several
+//! trips.
+use std::collections::{HashMap, HashSet};
+use std::time::SystemTime;
+use serde::Serialize;
+use stop_names::RegionGraph;
+use super::known_stops::Stop;
+use super::osm_lines::{Id, LineInfo};
+use super::telegram::{self, RunTelegram};
+use super::{pipeline, Junction, LineRun};
+
+/// Why a trip starts.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TripBoundary {
+    /// first trip of a run
+    RunStart,
+    /// the vehicle drives back along an edge of graph.json
+    Turnaround,
+    /// the same junction is reported with another direction
+    DirectionChange,
+    /// a junction of the trip is passed again
+    RepeatedJunction,
+    DestinationChange,
+}
+
+/// OSM route variant a trip follows.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct Variant {
+    pub relation: Id,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Trip {
+    #[serde(flatten)]
+    pub line_run: LineRun,
+    pub boundary: TripBoundary,
+    #[serde(skip)]
+    pub telegrams: Vec<RunTelegram>,
+    pub variant: Option<Variant>,
+}
+
+impl Trip {
+    pub fn junctions(&self) -> Vec<(SystemTime, Junction)> {
+        telegram::junctions(&self.telegrams)
+    }
+}
+
+/// Checks whether `current` starts a new trip after `previous`. `seen`
+/// contains the junctions of the trip so far.
+pub fn boundary(
+    previous: &RunTelegram,
+    current: &RunTelegram,
+    seen: &HashSet<Junction>,
+    graph: Option<&RegionGraph>,
+) -> Option<TripBoundary> {
+    if let (Some(previous_destination), Some(destination)) = (previous.destination_number, current.destination_number) {
+        if previous_destination != destination {
+            return Some(TripBoundary::DestinationChange);
+        }
+    }
+
+    if previous.junction == current.junction {
+        return match (previous.direction_request, current.direction_request) {
+            (Some(previous_direction), Some(direction)) if previous_direction != direction => {
+                Some(TripBoundary::DirectionChange)
+            }
+            _ => None,
+        };
+    }
+
+    if let Some(graph) = graph {
+        let backwards = graph.neighbours(&current.junction.0).contains(&previous.junction.0);
+        let forwards = graph.neighbours(&previous.junction.0).contains(&current.junction.0);
+        if backwards && !forwards {
+            return Some(TripBoundary::Turnaround);
+        }
+    }
+
+    if seen.contains(&current.junction) {
+        return Some(TripBoundary::RepeatedJunction);
+    }
+
+    None
+}
+
+/// Splits the telegrams of a run at every trip boundary.
+pub fn split_trips(line_run: &LineRun, telegrams: &[RunTelegram], graph: Option<&RegionGraph>) -> Vec<Trip> {
+    let mut trips = vec![];
+    let mut current = Trip {
+        line_run: line_run.clone(),
+        boundary: TripBoundary::RunStart,
+        telegrams: vec![],
+        variant: None,
+    };
+    let mut seen = HashSet::new();
+
+    for telegram in telegrams {
+        let boundary = current.telegrams.last()
+            .and_then(|previous| boundary(previous, telegram, &seen, graph));
+        if let Some(boundary) = boundary {
+            let next = Trip {
+                line_run: line_run.clone(),
+                boundary,
+                telegrams: vec![],
+                variant: None,
+            };
+            trips.push(std::mem::replace(&mut current, next));
+            seen.clear();
+        }
+        seen.insert(telegram.junction);
+        current.telegrams.push(*telegram);
+    }
+
+    if !current.telegrams.is_empty() {
+        trips.push(current);
+    }
+    trips
+}
+
+pub fn split_runs(runs: &[(LineRun, Vec<RunTelegram>)], graph: Option<&RegionGraph>) -> Vec<Trip> {
+    runs.iter()
+        .flat_map(|(line_run, telegrams)| split_trips(line_run, telegrams, graph))
+        .collect()
+}
+
+/// Labels every trip with the route variant of its line that passes its
+/// known stops in the same order. If several do, the one with the fewest
+/// other known stops wins.
+pub fn label_trips(
+    trips: &mut [Trip],
+    line_infos: &[LineInfo],
+    stops: &HashMap<Junction, Stop>,
+    max_distance: f64,
+) {
+    let variants = line_infos.iter()
+        .filter_map(|line_info| {
+            let known_stop_junctions = pipeline::find_known_stops(stops, line_info, max_distance)
+                .into_iter()
+                .map(|(_, junction, _)| junction)
+                .collect::<Vec<_>>();
+            let variant = Variant {
+                relation: line_info.relation,
+                name: line_info.name.clone(),
+            };
+            Some((line_info.line.clone()?, known_stop_junctions, variant))
+        })
+        .collect::<Vec<_>>();
+
+    for trip in trips {
+        let known_junctions = trip.junctions()
+            .into_iter()
+            .map(|(_, junction)| junction)
+            .filter(|junction| stops.contains_key(junction))
+            .collect::<Vec<_>>();
+        if known_junctions.len() < 2 {
+            continue;
+        }
+
+        trip.variant = variants.iter()
+            .filter(|(line, known_stop_junctions, _)|
+                *line == trip.line_run.line &&
+                pipeline::is_similar_sequence(&known_junctions, known_stop_junctions)
+            )
+            .min_by_key(|(_, known_stop_junctions, _)| known_stop_junctions.len())
+            .map(|(_, _, variant)| variant.clone());
+    }
+}
@@ -0,0 +1,623 @@
+//! Compact binary encoding of stops and graphs. Names and other strings are
+//! stored once in a string table, coordinates as fixed point numbers with
+//! seven decimals unless that would lose precision. Regions and reporting
+//! points are sorted and indexed, so `CompactStops` looks single reporting
+//! points up without decoding the rest.
+use chrono::prelude::{DateTime, NaiveDateTime, Utc};
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use hashbrown::HashMap;
+use crate::graph::{Edge, InterRegionalGraph, RegionGraph};
+use crate::{
+    DocumentMetaInformation, InterRegional, PositionStatus, R09Types, RegionMetaInformation,
+    RegionalTransmissionPositions, TelegramType, TransmissionPosition,
+};
+
+const STOPS_MAGIC: &[u8; 4] = b"SNS3";
+const GRAPH_MAGIC: &[u8; 4] = b"SNG2";
+/// string index of a missing string
+const NONE: u32 = u32::MAX;
+/// fixed point coordinates are stored in units of 1e-7 degrees
+const COORDINATE_SCALE: f64 = 1e7;
+/// flag of positions with raw f64 coordinates
+const RAW_COORDINATES: u8 = 1;
+/// flags of positions without latitude or longitude
+const NO_LAT: u8 = 2;
+const NO_LON: u8 = 4;
+/// flag of positions with an accuracy radius
+const ACCURACY: u8 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BinaryError {
+    BadMagic,
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinaryError::BadMagic => formatter.write_str("not a compact stop-names file"),
+            BinaryError::Truncated => formatter.write_str("unexpected end of data"),
+            BinaryError::Invalid(reason) => write!(formatter, "invalid data: {}", reason),
+        }
+    }
+}
+
+impl core::error::Error for BinaryError {}
+
+#[derive(Default)]
+struct Writer {
+    buffer: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn len(&self) -> u32 {
+        self.buffer.len() as u32
+    }
+
+    /// overwrites a u32 written before
+    fn patch(&mut self, at: u32, value: u32) {
+        let at = at as usize;
+        self.buffer[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// `base + length`, offsets and counts read from the data may overflow
+/// usize on 32 bit targets
+fn offset(base: usize, length: usize) -> Result<usize, BinaryError> {
+    base.checked_add(length).ok_or(BinaryError::Invalid("offset"))
+}
+
+/// size of a table of `count` entries of `size` bytes
+fn table(count: usize, size: usize) -> Result<usize, BinaryError> {
+    count.checked_mul(size).ok_or(BinaryError::Invalid("count"))
+}
+
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn at(data: &'a [u8], position: usize) -> Self {
+        Reader { data, position }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N], BinaryError> {
+        let end = offset(self.position, N)?;
+        let bytes = self.data.get(self.position..end)
+            .ok_or(BinaryError::Truncated)?;
+        self.position = end;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn skip(&mut self, length: usize) -> Result<(), BinaryError> {
+        self.position = offset(self.position, length)?;
+        Ok(())
+    }
+
+    fn u8(&mut self) -> Result<u8, BinaryError> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, BinaryError> {
+        Ok(u16::from_le_bytes(self.bytes()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, BinaryError> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, BinaryError> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, BinaryError> {
+        Ok(i32::from_le_bytes(self.bytes()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, BinaryError> {
+        Ok(i64::from_le_bytes(self.bytes()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, BinaryError> {
+        Ok(f64::from_le_bytes(self.bytes()?))
+    }
+}
+
+/// strings in order of their first use
+#[derive(Default)]
+struct StringTable {
+    strings: Vec<String>,
+    indices: HashMap<String, u32>,
+}
+
+impl StringTable {
+    fn index(&mut self, string: Option<&str>) -> u32 {
+        let Some(string) = string else {
+            return NONE;
+        };
+        if let Some(index) = self.indices.get(string) {
+            return *index;
+        }
+        let index = self.strings.len() as u32;
+        self.strings.push(string.to_string());
+        self.indices.insert(string.to_string(), index);
+        index
+    }
+
+    /// count, count + 1 offsets relative to the string data and the data
+    fn write(&self, writer: &mut Writer) {
+        writer.u32(self.strings.len() as u32);
+        let mut offset = 0;
+        writer.u32(offset);
+        for string in &self.strings {
+            offset += string.len() as u32;
+            writer.u32(offset);
+        }
+        for string in &self.strings {
+            writer.buffer.extend_from_slice(string.as_bytes());
+        }
+    }
+}
+
+fn fixed_point(coordinate: f64) -> Option<i32> {
+    let scaled = coordinate * COORDINATE_SCALE;
+    if !(i32::MIN as f64..=i32::MAX as f64).contains(&scaled) {
+        return None;
+    }
+    // rounded half away from zero, f64::round is not in core
+    let fixed = if scaled < 0.0 { scaled - 0.5 } else { scaled + 0.5 } as i32;
+    // only if decoding gives back exactly the same number
+    (fixed as f64 / COORDINATE_SCALE == coordinate).then_some(fixed)
+}
+
+fn telegram_type(value: u8) -> Result<TelegramType, BinaryError> {
+    Ok(match value {
+        0 => TelegramType::PreRegistration,
+        1 => TelegramType::Registration,
+        2 => TelegramType::DeRegistration,
+        3 => TelegramType::DoorClosed,
+        _ => return Err(BinaryError::Invalid("telegram type")),
+    })
+}
+
+fn position_status(value: u8) -> Result<PositionStatus, BinaryError> {
+    Ok(match value {
+        0 => PositionStatus::Surveyed,
+        1 => PositionStatus::Inferred,
+        2 => PositionStatus::EstimatedFromOsm,
+        3 => PositionStatus::Unknown,
+        _ => return Err(BinaryError::Invalid("position status")),
+    })
+}
+
+fn r09_type(value: u8) -> Result<Option<R09Types>, BinaryError> {
+    Ok(match value {
+        0 => None,
+        14 => Some(R09Types::R14),
+        16 => Some(R09Types::R16),
+        18 => Some(R09Types::R18),
+        _ => return Err(BinaryError::Invalid("r09 type")),
+    })
+}
+
+fn sorted_keys<K: Ord + Copy, V>(map: &HashMap<K, V>) -> Vec<K> {
+    let mut keys = map.keys().copied().collect::<Vec<K>>();
+    keys.sort_unstable();
+    keys
+}
+
+impl InterRegional {
+    /// Layout: magic, string table, document, meta, region directory and
+    /// per region a sorted reporting point index followed by the positions.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut strings = StringTable::default();
+        let mut body = Writer::default();
+
+        // document
+        body.u32(strings.index(Some(&self.document.schema_version)));
+        body.i64(self.document.date.timestamp());
+        body.u32(self.document.date.timestamp_subsec_nanos());
+        body.u32(strings.index(self.document.generator.as_deref()));
+        body.u32(strings.index(self.document.generator_version.as_deref()));
+
+        body.u32(self.meta.len() as u32);
+        for region in sorted_keys(&self.meta) {
+            let meta = &self.meta[&region];
+            body.u32(region);
+            body.u8(meta.frequency.is_some() as u8);
+            body.u64(meta.frequency.unwrap_or_default());
+            body.u32(strings.index(meta.city_name.as_deref()));
+            body.u8(meta.type_r09.as_ref().map_or(0, |r09_type| r09_type.clone() as u8));
+            body.u32(meta.lines.len() as u32);
+            for line in sorted_keys(&meta.lines) {
+                body.u16(line);
+                body.u32(strings.index(Some(&meta.lines[&line])));
+            }
+        }
+
+        // directory of (region, offset of its index), offsets are relative to
+        // the document and patched below
+        let regions = sorted_keys(&self.data);
+        body.u32(regions.len() as u32);
+        let directory = body.len();
+        for region in &regions {
+            body.u32(*region);
+            body.u32(0);
+        }
+
+        for (i, region) in regions.iter().enumerate() {
+            let positions = &self.data[region];
+            body.patch(directory + 8 * i as u32 + 4, body.len());
+
+            // index of (reporting point, offset of its positions, count)
+            let reporting_points = sorted_keys(positions);
+            body.u32(reporting_points.len() as u32);
+            let index = body.len();
+            for reporting_point in &reporting_points {
+                body.u32(*reporting_point);
+                body.u32(0);
+                body.u32(positions[reporting_point].len() as u32);
+            }
+
+            for (j, reporting_point) in reporting_points.iter().enumerate() {
+                body.patch(index + 12 * j as u32 + 4, body.len());
+                for position in &positions[reporting_point] {
+                    body.u32(strings.index(position.dhid.as_deref()));
+                    body.u32(strings.index(position.name.as_deref()));
+                    body.u8(position.telegram_type.clone() as u8);
+                    body.u8(position.direction);
+                    body.u8(position.status as u8);
+
+                    let coordinates = [position.lat, position.lon];
+                    let raw = coordinates.iter().flatten().any(|coordinate| fixed_point(*coordinate).is_none());
+                    body.u8(
+                        if raw { RAW_COORDINATES } else { 0 }
+                            | if position.lat.is_none() { NO_LAT } else { 0 }
+                            | if position.lon.is_none() { NO_LON } else { 0 }
+                            | if position.accuracy.is_some() { ACCURACY } else { 0 }
+                    );
+                    for coordinate in coordinates.into_iter().flatten() {
+                        match fixed_point(coordinate) {
+                            Some(fixed) if !raw => body.i32(fixed),
+                            _ => body.f64(coordinate),
+                        }
+                    }
+                    if let Some(accuracy) = position.accuracy {
+                        body.f64(accuracy);
+                    }
+                }
+            }
+        }
+
+        let mut output = Writer::default();
+        output.buffer.extend_from_slice(STOPS_MAGIC);
+        strings.write(&mut output);
+        output.u32(output.len() + 4);
+        output.buffer.extend_from_slice(&body.buffer);
+        output.buffer
+    }
+
+    pub fn from_binary(data: &[u8]) -> Result<InterRegional, BinaryError> {
+        CompactStops::new(data)?.decode()
+    }
+}
+
+/// Stops encoded by `InterRegional::to_binary`, decoded on access.
+#[derive(Debug, Clone, Copy)]
+pub struct CompactStops<'a> {
+    data: &'a [u8],
+    string_count: u32,
+    /// start of the string offsets
+    string_offsets: usize,
+    /// start of the string data
+    strings: usize,
+    /// start of the document, everything before belongs to the strings
+    body: usize,
+}
+
+impl<'a> CompactStops<'a> {
+    pub fn new(data: &'a [u8]) -> Result<CompactStops<'a>, BinaryError> {
+        if data.get(..4) != Some(STOPS_MAGIC) {
+            return Err(BinaryError::BadMagic);
+        }
+        let mut reader = Reader::at(data, 4);
+        let string_count = reader.u32()?;
+        let string_offsets = reader.position;
+        let mut end = Reader::at(data, offset(string_offsets, table(string_count as usize, 4)?)?);
+        let string_length = end.u32()? as usize;
+        let strings = end.position;
+        let mut body = Reader::at(data, offset(strings, string_length)?);
+        let body = body.u32()? as usize;
+        if body > data.len() {
+            return Err(BinaryError::Truncated);
+        }
+
+        Ok(CompactStops {
+            data,
+            string_count,
+            string_offsets,
+            strings,
+            body,
+        })
+    }
+
+    fn string(&self, index: u32) -> Result<Option<&'a str>, BinaryError> {
+        if index == NONE {
+            return Ok(None);
+        }
+        if index >= self.string_count {
+            return Err(BinaryError::Invalid("string index"));
+        }
+        let mut offsets = Reader::at(self.data, offset(self.string_offsets, table(index as usize, 4)?)?);
+        let (start, end) = (offsets.u32()? as usize, offsets.u32()? as usize);
+        let bytes = self.data.get(offset(self.strings, start)?..offset(self.strings, end)?)
+            .ok_or(BinaryError::Truncated)?;
+        core::str::from_utf8(bytes)
+            .map(Some)
+            .map_err(|_| BinaryError::Invalid("utf-8"))
+    }
+
+    fn owned_string(&self, reader: &mut Reader) -> Result<Option<String>, BinaryError> {
+        Ok(self.string(reader.u32()?)?.map(str::to_string))
+    }
+
+    /// position of the region directory
+    fn directory(&self) -> Result<Reader<'a>, BinaryError> {
+        let mut reader = Reader::at(self.data, offset(self.body, 24)?);
+        let meta_count = reader.u32()?;
+        for _ in 0..meta_count {
+            reader.skip(4 + 1 + 8 + 4 + 1)?;
+            let lines = reader.u32()? as usize;
+            reader.skip(table(lines, 6)?)?;
+        }
+        Ok(reader)
+    }
+
+    pub fn document(&self) -> Result<DocumentMetaInformation, BinaryError> {
+        let mut reader = Reader::at(self.data, self.body);
+        let schema_version = self.owned_string(&mut reader)?
+            .ok_or(BinaryError::Invalid("schema version"))?;
+        let (seconds, nanos) = (reader.i64()?, reader.u32()?);
+        Ok(DocumentMetaInformation {
+            schema_version,
+            date: NaiveDateTime::from_timestamp_opt(seconds, nanos)
+                .map(|date| DateTime::from_utc(date, Utc))
+                .ok_or(BinaryError::Invalid("date"))?,
+            generator: self.owned_string(&mut reader)?,
+            generator_version: self.owned_string(&mut reader)?,
+        })
+    }
+
+    pub fn meta(&self) -> Result<HashMap<u32, RegionMetaInformation>, BinaryError> {
+        let mut reader = Reader::at(self.data, offset(self.body, 24)?);
+        let mut meta = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let region = reader.u32()?;
+            let has_frequency = reader.u8()? != 0;
+            let frequency = reader.u64()?;
+            let city_name = self.owned_string(&mut reader)?;
+            let type_r09 = r09_type(reader.u8()?)?;
+            let mut lines = HashMap::new();
+            for _ in 0..reader.u32()? {
+                let line = reader.u16()?;
+                let reference = self.owned_string(&mut reader)?
+                    .ok_or(BinaryError::Invalid("line reference"))?;
+                lines.insert(line, reference);
+            }
+            meta.insert(region, RegionMetaInformation {
+                frequency: has_frequency.then_some(frequency),
+                city_name,
+                type_r09,
+                lines,
+            });
+        }
+        Ok(meta)
+    }
+
+    /// the regions with the absolute offsets of their index, sorted by id
+    fn region_offsets(&self) -> Result<Vec<(u32, usize)>, BinaryError> {
+        let mut reader = self.directory()?;
+        (0..reader.u32()?)
+            .map(|_| Ok((reader.u32()?, offset(self.body, reader.u32()? as usize)?)))
+            .collect()
+    }
+
+    pub fn regions(&self) -> Result<Vec<u32>, BinaryError> {
+        Ok(self.region_offsets()?.into_iter().map(|(region, _)| region).collect())
+    }
+
+    /// binary search of a sorted table of `count` entries of `size` bytes
+    /// that start with a u32 key, returns the reader after the key
+    fn search(&self, start: usize, count: usize, size: usize, key: u32) -> Result<Option<Reader<'a>>, BinaryError> {
+        let (mut low, mut high) = (0, count);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let mut reader = Reader::at(self.data, offset(start, table(middle, size)?)?);
+            match reader.u32()?.cmp(&key) {
+                core::cmp::Ordering::Less => low = middle + 1,
+                core::cmp::Ordering::Greater => high = middle,
+                core::cmp::Ordering::Equal => return Ok(Some(reader)),
+            }
+        }
+        Ok(None)
+    }
+
+    fn positions(&self, reader: &mut Reader, count: u32) -> Result<Vec<TransmissionPosition>, BinaryError> {
+        (0..count)
+            .map(|_| {
+                let dhid = self.owned_string(reader)?;
+                let name = self.owned_string(reader)?;
+                let telegram_type = telegram_type(reader.u8()?)?;
+                let direction = reader.u8()?;
+                let status = position_status(reader.u8()?)?;
+                let flags = reader.u8()?;
+                let mut coordinate = |missing: u8| -> Result<Option<f64>, BinaryError> {
+                    Ok(match (flags & missing != 0, flags & RAW_COORDINATES != 0) {
+                        (true, _) => None,
+                        (false, true) => Some(reader.f64()?),
+                        (false, false) => Some(reader.i32()? as f64 / COORDINATE_SCALE),
+                    })
+                };
+                let lat = coordinate(NO_LAT)?;
+                let lon = coordinate(NO_LON)?;
+                let accuracy = if flags & ACCURACY != 0 { Some(reader.f64()?) } else { None };
+                Ok(TransmissionPosition { dhid, name, telegram_type, direction, lat, lon, status, accuracy })
+            })
+            .collect()
+    }
+
+    /// the positions of a reporting point, decoding nothing else
+    pub fn look_up(&self, region_id: &u32, traffic_light: &u32) -> Result<Option<Vec<TransmissionPosition>>, BinaryError> {
+        let mut directory = self.directory()?;
+        let regions = directory.u32()? as usize;
+        let Some(mut region) = self.search(directory.position, regions, 8, *region_id)? else {
+            return Ok(None);
+        };
+
+        let mut index = Reader::at(self.data, offset(self.body, region.u32()? as usize)?);
+        let reporting_points = index.u32()? as usize;
+        let Some(mut entry) = self.search(index.position, reporting_points, 12, *traffic_light)? else {
+            return Ok(None);
+        };
+        let mut positions = Reader::at(self.data, offset(self.body, entry.u32()? as usize)?);
+        let count = entry.u32()?;
+        self.positions(&mut positions, count).map(Some)
+    }
+
+    pub fn region(&self, region_id: &u32) -> Result<Option<RegionalTransmissionPositions>, BinaryError> {
+        let Some((_, start)) = self.region_offsets()?.into_iter().find(|(region, _)| region == region_id) else {
+            return Ok(None);
+        };
+        let mut index = Reader::at(self.data, start);
+        let mut positions = HashMap::new();
+        for _ in 0..index.u32()? {
+            let reporting_point = index.u32()?;
+            let mut reader = Reader::at(self.data, offset(self.body, index.u32()? as usize)?);
+            let count = index.u32()?;
+            positions.insert(reporting_point, self.positions(&mut reader, count)?);
+        }
+        Ok(Some(positions))
+    }
+
+    pub fn decode(&self) -> Result<InterRegional, BinaryError> {
+        let mut data = HashMap::new();
+        for region in self.regions()? {
+            data.insert(region, self.region(&region)?.unwrap_or_default());
+        }
+        Ok(InterRegional {
+            document: self.document()?,
+            data,
+            meta: self.meta()?,
+        })
+    }
+}
+
+impl InterRegionalGraph {
+    /// Layout: magic and the sorted regions, junctions, directions and
+    /// edges, each prefixed by their count.
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.buffer.extend_from_slice(GRAPH_MAGIC);
+        writer.u32(self.regions.len() as u32);
+        for region in sorted_keys(&self.regions) {
+            let structure = &self.regions[&region].structure;
+            writer.u32(region);
+            writer.u32(structure.len() as u32);
+            for junction in sorted_keys(structure) {
+                let successors = &structure[&junction];
+                writer.u32(junction);
+                writer.u32(successors.len() as u32);
+                for direction in sorted_keys(successors) {
+                    let edges = &successors[&direction];
+                    writer.u8(direction);
+                    writer.u32(edges.len() as u32);
+                    for edge in edges {
+                        writer.u32(edge.target);
+                        writer.u32(edge.count);
+                        writer.u8(edge.median_travel_time.is_some() as u8);
+                        writer.f64(edge.median_travel_time.unwrap_or_default());
+                        writer.u32(edge.lines.len() as u32);
+                        for line in &edge.lines {
+                            writer.u16(*line);
+                        }
+                    }
+                }
+            }
+        }
+        writer.buffer
+    }
+
+    pub fn from_binary(data: &[u8]) -> Result<InterRegionalGraph, BinaryError> {
+        if data.get(..4) != Some(GRAPH_MAGIC) {
+            return Err(BinaryError::BadMagic);
+        }
+        let mut reader = Reader::at(data, 4);
+        let mut regions = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let region = reader.u32()?;
+            let mut graph = RegionGraph::default();
+            for _ in 0..reader.u32()? {
+                let junction = reader.u32()?;
+                let successors = graph.structure.entry(junction).or_default();
+                for _ in 0..reader.u32()? {
+                    let direction = reader.u8()?;
+                    let mut edges = vec![];
+                    for _ in 0..reader.u32()? {
+                        let target = reader.u32()?;
+                        let count = reader.u32()?;
+                        let has_median = reader.u8()? != 0;
+                        let median = reader.f64()?;
+                        let lines = (0..reader.u32()?)
+                            .map(|_| reader.u16())
+                            .collect::<Result<Vec<u16>, BinaryError>>()?;
+                        edges.push(Edge {
+                            target,
+                            count,
+                            median_travel_time: has_median.then_some(median),
+                            lines,
+                        });
+                    }
+                    successors.insert(direction, edges);
+                }
+            }
+            regions.insert(region, graph);
+        }
+        Ok(InterRegionalGraph { regions })
+    }
+}
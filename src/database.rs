@@ -0,0 +1,194 @@
+use arc_swap::ArcSwap;
+use chrono::prelude::{DateTime, Utc};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+
+use crate::{InterRegional, InterRegionalGraph};
+
+/// one consistent version of the stops and graph files
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub stops: InterRegional,
+    pub graph: Option<InterRegionalGraph>,
+    /// hash of the stops file content
+    pub stops_checksum: u64,
+    /// hash of the graph file content
+    pub graph_checksum: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Read(path, e) => write!(formatter, "cannot read {}: {}", path.display(), e),
+            LoadError::Parse(path, e) => write!(formatter, "cannot parse {}: {}", path.display(), e),
+            LoadError::Invalid(reason) => write!(formatter, "invalid data: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+fn read<T: serde::de::DeserializeOwned>(path: &Path) -> Result<(T, u64), LoadError> {
+    let content = fs::read(path)
+        .map_err(|e| LoadError::Read(path.to_path_buf(), e))?;
+    let value = serde_json::from_slice(&content)
+        .map_err(|e| LoadError::Parse(path.to_path_buf(), e))?;
+
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    Ok((value, hasher.finish()))
+}
+
+impl Snapshot {
+    pub fn load(stops: &Path, graph: Option<&Path>) -> Result<Snapshot, LoadError> {
+        let (stops, stops_checksum) = read(stops)?;
+        let graph = graph.map(read).transpose()?;
+        Ok(Snapshot {
+            stops,
+            stops_checksum,
+            graph_checksum: graph.as_ref().map(|(_, checksum)| *checksum),
+            graph: graph.map(|(graph, _)| graph),
+        })
+    }
+
+    /// see `InterRegional::validate`
+    pub fn validate(&self) -> Result<(), LoadError> {
+        self.stops.validate(self.graph.as_ref())
+            .map_err(LoadError::Invalid)
+    }
+
+    pub fn schema_version(&self) -> &str {
+        &self.stops.document.schema_version
+    }
+
+    pub fn date(&self) -> DateTime<Utc> {
+        self.stops.document.date
+    }
+}
+
+struct Inner {
+    stops_path: PathBuf,
+    graph_path: Option<PathBuf>,
+    current: ArcSwap<Snapshot>,
+    last_error: Mutex<Option<String>>,
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+/// Shared handle to the stops and graph files. Readers get the snapshot
+/// that was current when they asked, reloads swap in a new one only if it
+/// validates.
+#[derive(Clone)]
+pub struct StopDatabase {
+    inner: Arc<Inner>,
+}
+
+impl fmt::Debug for StopDatabase {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_struct("StopDatabase")
+            .field("stops_path", &self.inner.stops_path)
+            .field("graph_path", &self.inner.graph_path)
+            .finish()
+    }
+}
+
+impl StopDatabase {
+    pub fn open(stops: impl AsRef<Path>, graph: Option<impl AsRef<Path>>) -> Result<StopDatabase, LoadError> {
+        let stops_path = stops.as_ref().to_path_buf();
+        let graph_path = graph.map(|graph| graph.as_ref().to_path_buf());
+        let snapshot = Snapshot::load(&stops_path, graph_path.as_deref())?;
+        snapshot.validate()?;
+
+        Ok(StopDatabase {
+            inner: Arc::new(Inner {
+                stops_path,
+                graph_path,
+                current: ArcSwap::from_pointee(snapshot),
+                last_error: Mutex::new(None),
+                watcher: Mutex::new(None),
+            }),
+        })
+    }
+
+    /// the current snapshot
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.inner.current.load_full()
+    }
+
+    /// Loads and validates the files again. On errors the old snapshot is
+    /// kept and the error is remembered for `last_error`.
+    pub fn reload(&self) -> Result<(), LoadError> {
+        let result = Snapshot::load(&self.inner.stops_path, self.inner.graph_path.as_deref())
+            .and_then(|snapshot| snapshot.validate().map(|_| snapshot));
+        let mut last_error = self.inner.last_error.lock().unwrap();
+        match result {
+            Ok(snapshot) => {
+                self.inner.current.store(Arc::new(snapshot));
+                *last_error = None;
+                Ok(())
+            }
+            Err(e) => {
+                *last_error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// why the last reload was rejected, `None` if it succeeded
+    pub fn last_error(&self) -> Option<String> {
+        self.inner.last_error.lock().unwrap().clone()
+    }
+
+    /// Reloads whenever one of the files changes, until the last handle is
+    /// dropped. The directories are watched, so files replaced by a rename
+    /// are picked up as well.
+    pub fn watch(&self) -> Result<(), notify::Error> {
+        let files = [Some(&self.inner.stops_path), self.inner.graph_path.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+            .collect::<Vec<PathBuf>>();
+
+        let database = Arc::downgrade(&self.inner);
+        let watched = files.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if event.kind.is_access() || !event.paths.iter().any(|path| watched.contains(path)) {
+                return;
+            }
+            if let Some(inner) = Weak::upgrade(&database) {
+                StopDatabase { inner }.reload().ok();
+            }
+        })?;
+
+        for file in &files {
+            let directory = file.parent().unwrap_or(Path::new("."));
+            watcher.watch(directory, RecursiveMode::NonRecursive)?;
+        }
+        *self.inner.watcher.lock().unwrap() = Some(watcher);
+        Ok(())
+    }
+
+    pub fn schema_version(&self) -> String {
+        self.snapshot().schema_version().to_string()
+    }
+
+    pub fn date(&self) -> DateTime<Utc> {
+        self.snapshot().date()
+    }
+}
@@ -0,0 +1,124 @@
+use serde_json::{json, Value};
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::graph::{Edge, RegionGraph};
+use crate::{RegionalTransmissionPositions, TelegramType, TransmissionPosition};
+
+/// stroke colours of the lines, picked by R09 line number
+pub const LINE_COLOURS: [&str; 10] = [
+    "#e6194b", "#3cb44b", "#4363d8", "#f58231", "#911eb4",
+    "#42d4f4", "#f032e6", "#9a6324", "#800000", "#000075",
+];
+
+/// stroke colour of edges without any observed line
+pub const UNKNOWN_LINE_COLOUR: &str = "#808080";
+
+/// stroke colour of a line
+pub fn line_colour(line: u16) -> &'static str {
+    LINE_COLOURS[line as usize % LINE_COLOURS.len()]
+}
+
+/// the door closed position if there is one, like
+/// `InterRegional::get_approximate_position`
+fn position<'a>(positions: &'a RegionalTransmissionPositions, junction: &u32) -> Option<&'a TransmissionPosition> {
+    let candidates = positions.get(junction)?;
+    candidates.iter()
+        .find(|position| position.telegram_type == TelegramType::DoorClosed && position.coordinates().is_some())
+        .or_else(|| candidates.iter().find(|position| position.coordinates().is_some()))
+        .or(candidates.first())
+}
+
+fn coordinates(positions: &RegionalTransmissionPositions, junction: &u32) -> Option<(f64, f64)> {
+    position(positions, junction)?.coordinates()
+}
+
+/// edges ordered by junction, direction and target
+fn sorted_edges(graph: &RegionGraph) -> Vec<(u32, u8, &Edge)> {
+    let mut edges = graph.structure.keys()
+        .flat_map(|junction| graph.edges(junction).map(|(direction, edge)| (*junction, direction, edge)))
+        .collect::<Vec<_>>();
+    edges.sort_by_key(|(junction, direction, edge)| (*junction, *direction, edge.target));
+    edges
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl RegionGraph {
+    /// Graphviz digraph of the region. Nodes are labelled with their name
+    /// from stops.json, edges with direction and count. Edges with an
+    /// endpoint without coordinates are drawn red and dashed.
+    pub fn to_dot(&self, positions: &RegionalTransmissionPositions) -> String {
+        let edges = sorted_edges(self);
+        let mut junctions = edges.iter()
+            .flat_map(|(junction, _, edge)| [*junction, edge.target])
+            .collect::<Vec<u32>>();
+        junctions.sort_unstable();
+        junctions.dedup();
+
+        let mut dot = String::from("digraph region {\n");
+        for junction in junctions {
+            let label = match position(positions, &junction).and_then(|position| position.name.as_deref()) {
+                Some(name) => format!("{}\\n{}", escape(name), junction),
+                None => junction.to_string(),
+            };
+            writeln!(dot, "    \"{}\" [label=\"{}\"];", junction, label).ok();
+        }
+        for (junction, direction, edge) in edges {
+            let located = coordinates(positions, &junction).is_some() && coordinates(positions, &edge.target).is_some();
+            let style = if located { "" } else { ", color=red, style=dashed" };
+            writeln!(dot, "    \"{}\" -> \"{}\" [label=\"{} ({})\"{}];", junction, edge.target, direction, edge.count, style).ok();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// GeoJSON FeatureCollection with a LineString per edge and line,
+    /// coloured by line. Edges with an endpoint without coordinates have no
+    /// geometry and are flagged with `missing_coordinates`.
+    pub fn to_geojson(&self, positions: &RegionalTransmissionPositions) -> Value {
+        let mut features = vec![];
+        for (junction, direction, edge) in sorted_edges(self) {
+            let geometry = match (coordinates(positions, &junction), coordinates(positions, &edge.target)) {
+                (Some((from_lat, from_lon)), Some((to_lat, to_lon))) => json!({
+                    "type": "LineString",
+                    "coordinates": [[from_lon, from_lat], [to_lon, to_lat]],
+                }),
+                _ => Value::Null,
+            };
+            let lines = if edge.lines.is_empty() {
+                vec![None]
+            } else {
+                edge.lines.iter().copied().map(Some).collect()
+            };
+
+            for line in lines {
+                features.push(json!({
+                    "type": "Feature",
+                    "geometry": geometry,
+                    "properties": {
+                        "from": junction,
+                        "to": edge.target,
+                        "direction": direction,
+                        "count": edge.count,
+                        "median_travel_time": edge.median_travel_time,
+                        "line": line,
+                        "stroke": line.map_or(UNKNOWN_LINE_COLOUR, line_colour),
+                        "missing_coordinates": geometry.is_null(),
+                    },
+                }));
+            }
+        }
+
+        json!({
+            "type": "FeatureCollection",
+            "features": features,
+        })
+    }
+}
@@ -0,0 +1,44 @@
+//! Distances between positions, these need the float functions of std.
+use serde::Serialize;
+
+use crate::{InterRegional, TransmissionPosition};
+
+/// mean earth radius in meters
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// a position found by `InterRegional::nearest`
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct NearestPosition {
+    pub reporting_point: u32,
+    /// distance in meters
+    pub distance: f64,
+    pub position: TransmissionPosition,
+}
+
+/// haversine distance in meters between two (lat, lon) pairs
+pub fn distance(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat2, lon2) = (to.0.to_radians(), to.1.to_radians());
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+impl InterRegional {
+    /// The `limit` positions of the region closest to the coordinates,
+    /// `None` if the region is unknown. Positions without coordinates are
+    /// left out.
+    pub fn nearest(&self, region_id: &u32, lat: f64, lon: f64, limit: usize) -> Option<Vec<NearestPosition>> {
+        let mut nearest = self.data.get(region_id)?
+            .iter()
+            .flat_map(|(reporting_point, positions)| positions.iter().filter_map(|position| Some(NearestPosition {
+                reporting_point: *reporting_point,
+                distance: distance((lat, lon), position.coordinates()?),
+                position: position.clone(),
+            })))
+            .collect::<Vec<_>>();
+        nearest.sort_by(|a, b| a.distance.total_cmp(&b.distance).then(a.reporting_point.cmp(&b.reporting_point)));
+        nearest.truncate(limit);
+        Some(nearest)
+    }
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use hashbrown::HashMap;
+
+/// an observed transition to the next reporting point
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Edge {
+    pub target: u32,
+    /// how often the transition was observed
+    pub count: u32,
+    /// median travel time in seconds
+    pub median_travel_time: Option<f64>,
+    /// R09 line numbers that use the edge
+    pub lines: Vec<u16>,
+}
+
+/// observed successor reporting points for each direction of a reporting point
+pub type Successors = HashMap<u8, Vec<Edge>>;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct RegionGraph {
+    #[serde(deserialize_with = "deserialize_structure")]
+    pub structure: HashMap<u32, Successors>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(transparent)]
+pub struct InterRegionalGraph {
+    pub regions: HashMap<u32, RegionGraph>,
+}
+
+/// graph.json used to store a single successor per direction
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EdgesRepresentation {
+    Single(u32),
+    Multiple(Vec<Edge>),
+}
+
+fn deserialize_structure<'de, D>(deserializer: D) -> Result<HashMap<u32, Successors>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let structure = HashMap::<u32, HashMap<u8, EdgesRepresentation>>::deserialize(deserializer)?;
+    Ok(structure
+        .into_iter()
+        .map(|(reporting_point, successors)| {
+            let successors = successors
+                .into_iter()
+                .map(|(direction, edges)| match edges {
+                    EdgesRepresentation::Single(target) => (direction, vec![Edge::single(target)]),
+                    EdgesRepresentation::Multiple(edges) => (direction, edges),
+                })
+                .collect();
+            (reporting_point, successors)
+        })
+        .collect())
+}
+
+impl Edge {
+    /// edge of a single successor graph, counted once
+    pub fn single(target: u32) -> Edge {
+        Edge {
+            target,
+            count: 1,
+            median_travel_time: None,
+            lines: vec![],
+        }
+    }
+}
+
+/// graph with only the given successor per direction
+impl From<HashMap<u32, HashMap<u8, u32>>> for RegionGraph {
+    fn from(structure: HashMap<u32, HashMap<u8, u32>>) -> Self {
+        RegionGraph {
+            structure: structure
+                .into_iter()
+                .map(|(reporting_point, successors)| {
+                    let successors = successors
+                        .into_iter()
+                        .map(|(direction, target)| (direction, vec![Edge::single(target)]))
+                        .collect();
+                    (reporting_point, successors)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl RegionGraph {
+    pub fn successors(&self, reporting_point: &u32) -> Option<&Successors> {
+        self.structure.get(reporting_point)
+    }
+
+    /// all edges leaving the reporting point with their direction
+    pub fn edges(&self, reporting_point: &u32) -> impl Iterator<Item = (u8, &Edge)> {
+        self.successors(reporting_point)
+            .into_iter()
+            .flat_map(|successors| {
+                successors
+                    .iter()
+                    .flat_map(|(direction, edges)| edges.iter().map(move |edge| (*direction, edge)))
+            })
+    }
+
+    /// the most often observed successor, the lowest one on ties
+    pub fn most_likely_successor(&self, reporting_point: &u32, direction: &u8) -> Option<u32> {
+        self.successors(reporting_point)?
+            .get(direction)?
+            .iter()
+            .max_by_key(|edge| (edge.count, core::cmp::Reverse(edge.target)))
+            .map(|edge| edge.target)
+    }
+
+    /// all distinct reporting points that directly follow the given one
+    pub fn neighbours(&self, reporting_point: &u32) -> Vec<u32> {
+        let mut neighbours = self
+            .edges(reporting_point)
+            .map(|(_, edge)| edge.target)
+            .collect::<Vec<u32>>();
+
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+}
+
+impl InterRegionalGraph {
+    pub fn extract(&self, region_id: &u32) -> Option<RegionGraph> {
+        self.regions.get(region_id).cloned()
+    }
+}
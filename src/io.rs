@@ -0,0 +1,96 @@
+//! Reading and writing files, only available with the `std` feature.
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+
+use crate::{InterRegional, InterRegionalGraph, ReceiverRegistry};
+
+impl InterRegional {
+    pub fn from(file: &str) -> Option<InterRegional> {
+        let data = fs::read_to_string(file);
+
+        if data.is_err() {
+            return None;
+        }
+
+        serde_json::from_str(&data.unwrap()).ok()
+    }
+
+    pub fn write(&self, file: &str) {
+        fs::remove_file(file).ok();
+        let mut output = File::create(file)
+            .expect("cannot create or open file!");
+
+        let json_data = serde_json::to_string_pretty(&self)
+            .expect("cannot serialize structs!");
+
+        output.write_all(json_data.as_bytes())
+            .expect("cannot write to file!");
+    }
+
+    pub fn read_binary(file: &str) -> Option<InterRegional> {
+        InterRegional::from_binary(&fs::read(file).ok()?).ok()
+    }
+
+    pub fn write_binary(&self, file: &str) {
+        fs::write(file, self.to_binary())
+            .expect("cannot write to file!");
+    }
+}
+
+impl InterRegionalGraph {
+    pub fn from(file: &str) -> Option<InterRegionalGraph> {
+        let data = fs::read_to_string(file);
+
+        if data.is_err() {
+            return None;
+        }
+
+        serde_json::from_str(&data.unwrap()).ok()
+    }
+
+    pub fn write(&self, file: &str) {
+        fs::remove_file(file).ok();
+        let mut output = File::create(file)
+            .expect("cannot create or open file!");
+
+        let json_data = serde_json::to_string_pretty(&self)
+            .expect("cannot serialize structs!");
+
+        output.write_all(json_data.as_bytes())
+            .expect("cannot write to file!");
+    }
+
+    pub fn read_binary(file: &str) -> Option<InterRegionalGraph> {
+        InterRegionalGraph::from_binary(&fs::read(file).ok()?).ok()
+    }
+
+    pub fn write_binary(&self, file: &str) {
+        fs::write(file, self.to_binary())
+            .expect("cannot write to file!");
+    }
+}
+
+impl ReceiverRegistry {
+    pub fn from(file: &str) -> Option<ReceiverRegistry> {
+        let data = fs::read_to_string(file);
+
+        if data.is_err() {
+            return None;
+        }
+
+        serde_json::from_str(&data.unwrap()).ok()
+    }
+
+    pub fn write(&self, file: &str) {
+        fs::remove_file(file).ok();
+        let mut output = File::create(file)
+            .expect("cannot create or open file!");
+
+        let json_data = serde_json::to_string_pretty(&self)
+            .expect("cannot serialize structs!");
+
+        output.write_all(json_data.as_bytes())
+            .expect("cannot write to file!");
+    }
+}
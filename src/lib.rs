@@ -1,15 +1,51 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(all(test, feature = "std"))]
 mod tests;
+mod binary;
+#[cfg(feature = "database")]
+mod database;
+mod export;
+#[cfg(feature = "std")]
+mod geo;
+mod graph;
+#[cfg(feature = "std")]
+mod io;
+mod line;
+#[cfg(feature = "python")]
+mod python;
+mod receivers;
+mod routing;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+/// the maps of the public types, the same with and without std
+pub use hashbrown::{HashMap, HashSet};
+
+pub use binary::{BinaryError, CompactStops};
+#[cfg(feature = "database")]
+pub use database::{LoadError, Snapshot, StopDatabase};
+pub use export::{line_colour, LINE_COLOURS, UNKNOWN_LINE_COLOUR};
+#[cfg(feature = "std")]
+pub use geo::{distance, NearestPosition};
+pub use graph::{Edge, InterRegionalGraph, RegionGraph, Successors};
+pub use line::{Line, LineReferences, Run};
+pub use receivers::{Receiver, ReceiverRegistry};
+pub use routing::{Route, Weight, UNKNOWN_TRAVEL_TIME};
+#[cfg(feature = "wasm")]
+pub use wasm::Stops;
 
 use chrono::prelude::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use std::hash::Hash;
-use std::hash::Hasher;
-use std::collections::HashMap;
-use std::fs;
-use std::fs::File;
-use std::io::Write;
-use std::fmt;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::hash::Hash;
+use core::hash::Hasher;
+use core::fmt;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum R09Types {
@@ -26,22 +62,84 @@ pub enum TelegramType {
     DoorClosed = 3,
 }
 
+/// where the coordinates of a position come from
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PositionStatus {
+    /// measured on site
+    #[default]
+    Surveyed = 0,
+    /// derived from telegrams and the positions around it
+    Inferred = 1,
+    /// placed on OpenStreetMap tracks
+    EstimatedFromOsm = 2,
+    /// the reporting point exists, but nobody knows where
+    Unknown = 3,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(from = "StoredPosition")]
 pub struct TransmissionPosition {
-    #[serde(alias = "DHID")]
     pub dhid: Option<String>,
     pub name: Option<String>,
     pub telegram_type: TelegramType,
     pub direction: u8,
-    pub lat: f64,
-    pub lon: f64,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub status: PositionStatus,
+    /// radius in meters around the coordinates the reporting point is in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accuracy: Option<f64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+/// Positions as written by older versions, without status they count as
+/// surveyed if they have coordinates and as unknown otherwise.
+#[derive(Deserialize)]
+struct StoredPosition {
+    #[serde(alias = "DHID")]
+    dhid: Option<String>,
+    name: Option<String>,
+    telegram_type: TelegramType,
+    direction: u8,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    status: Option<PositionStatus>,
+    accuracy: Option<f64>,
+}
+
+impl From<StoredPosition> for TransmissionPosition {
+    fn from(position: StoredPosition) -> Self {
+        let located = position.lat.is_some() && position.lon.is_some();
+        TransmissionPosition {
+            dhid: position.dhid,
+            name: position.name,
+            telegram_type: position.telegram_type,
+            direction: position.direction,
+            lat: position.lat,
+            lon: position.lon,
+            status: position.status.unwrap_or(if located { PositionStatus::Surveyed } else { PositionStatus::Unknown }),
+            accuracy: position.accuracy,
+        }
+    }
+}
+
+impl TransmissionPosition {
+    /// latitude and longitude, `None` if the location is unknown
+    pub fn coordinates(&self) -> Option<(f64, f64)> {
+        if self.status == PositionStatus::Unknown {
+            return None;
+        }
+        self.lat.zip(self.lon)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
 pub struct RegionMetaInformation {
     pub frequency: Option<u64>,
     pub city_name: Option<String>,
     pub type_r09: Option<R09Types>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub lines: LineReferences,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -52,6 +150,9 @@ pub struct DocumentMetaInformation {
     pub generator_version: Option<String>,
 }
 
+/// major schema version of stops.json this crate understands
+pub const SUPPORTED_SCHEMA_VERSION: &str = "1";
+
 pub type RegionalTransmissionPositions = HashMap<u32, Vec<TransmissionPosition>>;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -68,28 +169,6 @@ pub struct Region {
 }
 
 impl InterRegional {
-    pub fn from(file: &str) -> Option<InterRegional> {
-        let data = fs::read_to_string(file);
-
-        if data.is_err() {
-            return None;
-        }
-
-        serde_json::from_str(&data.unwrap()).ok()
-    }
-
-    pub fn write(&self, file: &str) {
-        fs::remove_file(file).ok();
-        let mut output = File::create(file)
-            .expect("cannot create or open file!");
-
-        let json_data = serde_json::to_string_pretty(&self)
-            .expect("cannot serialize structs!");
-
-        output.write_all(json_data.as_bytes())
-            .expect("cannot write to file!");
-    }
-
     pub fn extract(&self, region_id: &u32) -> Option<Region> {
         let data = self.data.get(region_id);
         let meta = self.meta.get(region_id);
@@ -111,7 +190,7 @@ impl InterRegional {
     ) -> Option<Vec<TransmissionPosition>> {
         match self.data.get(region_id) {
             Some(region) => {
-                return region.get(traffic_light).map(|x| x.clone());
+                region.get(traffic_light).cloned()
             }
             None => None,
         }
@@ -126,23 +205,79 @@ impl InterRegional {
 
         match stop_list {
             Some(possbile_stations) => {
-                if possbile_stations.len() == 0 {
+                if possbile_stations.is_empty() {
                     return None;
                 }
 
-                let selected_position = possbile_stations[0].clone();
+                // positions with coordinates first, the rest only shows the status
+                let selected_position = possbile_stations.iter()
+                    .find(|position| position.coordinates().is_some())
+                    .unwrap_or(&possbile_stations[0])
+                    .clone();
 
                 for position in possbile_stations {
-                    if position.telegram_type == TelegramType::DoorClosed {
+                    if position.telegram_type == TelegramType::DoorClosed && position.coordinates().is_some() {
                         return Some(position);
                     }
                 }
 
-                Some(selected_position.clone())
+                Some(selected_position)
             }
             None => None,
         }
     }
+
+    /// Checks the schema version, the coordinates against their status and
+    /// that the graph only covers regions of the stops.
+    pub fn validate(&self, graph: Option<&InterRegionalGraph>) -> Result<(), String> {
+        let schema_version = &self.document.schema_version;
+        if schema_version.split('.').next() != Some(SUPPORTED_SCHEMA_VERSION) {
+            return Err(format!("unsupported schema version {}", schema_version));
+        }
+
+        for (region, positions) in &self.data {
+            for (reporting_point, positions) in positions {
+                for position in positions {
+                    match (position.lat, position.lon) {
+                        (Some(_), Some(_)) if position.status == PositionStatus::Unknown => return Err(format!(
+                            "reporting point {} of region {} has coordinates but is Unknown",
+                            reporting_point, region,
+                        )),
+                        (Some(lat), Some(lon)) => if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+                            return Err(format!(
+                                "reporting point {} of region {} is at {}, {}",
+                                reporting_point, region, lat, lon,
+                            ));
+                        },
+                        (None, None) => if position.status != PositionStatus::Unknown {
+                            return Err(format!(
+                                "reporting point {} of region {} has no coordinates but is {:?}",
+                                reporting_point, region, position.status,
+                            ));
+                        },
+                        _ => return Err(format!(
+                            "reporting point {} of region {} has only one coordinate",
+                            reporting_point, region,
+                        )),
+                    }
+                    if position.accuracy.is_some_and(|accuracy| !(accuracy >= 0.0 && accuracy.is_finite())) {
+                        return Err(format!(
+                            "reporting point {} of region {} has an accuracy of {:?}",
+                            reporting_point, region, position.accuracy,
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Some(graph) = graph {
+            if let Some(region) = graph.regions.keys().find(|region| !self.data.contains_key(*region)) {
+                return Err(format!("graph of unknown region {}", region));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl<'de> serde::Deserialize<'de> for R09Types {
@@ -155,7 +290,7 @@ impl<'de> serde::Deserialize<'de> for R09Types {
         impl<'de> serde::de::Visitor<'de> for R09TypesVisitor {
             type Value = R09Types;
 
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
                 write!(formatter, "an integer or string representing a R09Type")
             }
 
@@ -206,6 +341,17 @@ impl fmt::Display for R09Types {
     }
 }
 
+impl fmt::Display for PositionStatus {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(match self {
+            PositionStatus::Surveyed => "surveyed",
+            PositionStatus::Inferred => "inferred",
+            PositionStatus::EstimatedFromOsm => "estimated_from_osm",
+            PositionStatus::Unknown => "unknown",
+        })
+    }
+}
+
 impl<'de> serde::Deserialize<'de> for TelegramType {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -216,7 +362,7 @@ impl<'de> serde::Deserialize<'de> for TelegramType {
         impl<'de> serde::de::Visitor<'de> for TelegramTypeVisitor {
             type Value = TelegramType;
 
-            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
                 write!(formatter, "an integer or string representing a R09Type")
             }
             fn visit_u64<E: serde::de::Error>(self, n: u64) -> Result<TelegramType, E> {
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+
+use alloc::string::{String, ToString};
+use core::cmp::Ordering;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+
+use hashbrown::HashMap;
+
+/// maps the R09 line numbers of a region to their public line references
+pub type LineReferences = HashMap<u16, String>;
+
+/// A line is identified by the number transmitted in R09 telegrams. The
+/// public reference, like "EV3" for a replacement service, is only carried
+/// along for display and does not take part in comparisons.
+#[derive(Serialize, Debug, Clone)]
+pub struct Line {
+    pub number: u16,
+    pub reference: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Ord, PartialOrd, Clone, Copy)]
+pub struct Run(pub u16);
+
+impl Line {
+    pub fn new(number: u16) -> Line {
+        Line {
+            number,
+            reference: None,
+        }
+    }
+
+    /// public reference, falling back to the R09 line number
+    pub fn reference(&self) -> String {
+        self.reference
+            .clone()
+            .unwrap_or_else(|| self.number.to_string())
+    }
+
+    /// Resolves a public line reference using the mapping of a region.
+    /// Numeric references without a mapping are taken as R09 line number.
+    pub fn from_reference(reference: &str, lines: &LineReferences) -> Option<Line> {
+        let number = lines
+            .iter()
+            .find(|(_, line_reference)| *line_reference == reference)
+            .map(|(number, _)| *number)
+            .or_else(|| {
+                reference
+                    .parse()
+                    .ok()
+                    .filter(|number| !lines.contains_key(number))
+            })?;
+
+        Some(Line {
+            number,
+            reference: Some(reference.to_string()),
+        })
+    }
+
+    /// Line for a R09 line number, with the public reference of the region.
+    pub fn from_number(number: u16, lines: &LineReferences) -> Line {
+        Line {
+            number,
+            reference: lines.get(&number).cloned(),
+        }
+    }
+}
+
+impl PartialEq for Line {
+    fn eq(&self, other: &Self) -> bool {
+        self.number == other.number
+    }
+}
+
+impl Eq for Line {}
+
+impl Hash for Line {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.number.hash(state);
+    }
+}
+
+impl PartialOrd for Line {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Line {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.number.cmp(&other.number)
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(&self.reference())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Line {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // telegrams only carry the number, documents the full struct
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum LineRepresentation {
+            Number(u16),
+            Full {
+                number: u16,
+                reference: Option<String>,
+            },
+        }
+
+        Ok(match LineRepresentation::deserialize(deserializer)? {
+            LineRepresentation::Number(number) => Line::new(number),
+            LineRepresentation::Full { number, reference } => Line { number, reference },
+        })
+    }
+}
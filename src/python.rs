@@ -0,0 +1,231 @@
+//! pyo3 bindings, so python scripts share the parsing and lookups of this
+//! crate instead of reading stops.json by hand.
+use pyo3::exceptions::{PyIOError, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use std::collections::HashMap;
+use std::fs;
+
+use crate::{InterRegional, InterRegionalGraph, NearestPosition, RegionGraph, TransmissionPosition, SUPPORTED_SCHEMA_VERSION};
+
+/// a transmission position, the telegram type as its R09 number and the
+/// status as in stops.json
+#[pyclass(name = "TransmissionPosition", frozen, get_all)]
+struct Position {
+    dhid: Option<String>,
+    name: Option<String>,
+    telegram_type: u8,
+    direction: u8,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    status: String,
+    accuracy: Option<f64>,
+}
+
+impl From<TransmissionPosition> for Position {
+    fn from(position: TransmissionPosition) -> Self {
+        Position {
+            dhid: position.dhid,
+            name: position.name,
+            telegram_type: position.telegram_type as u8,
+            direction: position.direction,
+            lat: position.lat,
+            lon: position.lon,
+            status: position.status.to_string(),
+            accuracy: position.accuracy,
+        }
+    }
+}
+
+#[pymethods]
+impl Position {
+    fn __repr__(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "None".to_string());
+        format!(
+            "TransmissionPosition(name={}, telegram_type={}, direction={}, lat={}, lon={}, status={:?})",
+            optional(self.name.as_ref().map(|name| format!("{:?}", name))),
+            self.telegram_type,
+            self.direction,
+            optional(self.lat.map(|lat| lat.to_string())),
+            optional(self.lon.map(|lon| lon.to_string())),
+            self.status,
+        )
+    }
+}
+
+fn positions(positions: Vec<TransmissionPosition>) -> Vec<Position> {
+    positions.into_iter().map(Position::from).collect()
+}
+
+fn read(path: &str) -> PyResult<Vec<u8>> {
+    fs::read(path).map_err(|e| PyIOError::new_err(format!("cannot read {}: {}", path, e)))
+}
+
+fn write(path: &str, content: &[u8]) -> PyResult<()> {
+    fs::write(path, content).map_err(|e| PyIOError::new_err(format!("cannot write {}: {}", path, e)))
+}
+
+fn parse<T: serde::de::DeserializeOwned>(json: &[u8]) -> PyResult<T> {
+    serde_json::from_slice(json).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> PyResult<String> {
+    serde_json::to_string_pretty(value).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// the content of a stops.json
+#[pyclass(name = "Stops")]
+struct Stops {
+    stops: InterRegional,
+}
+
+#[pymethods]
+impl Stops {
+    #[staticmethod]
+    fn load(path: &str) -> PyResult<Stops> {
+        Ok(Stops { stops: parse(&read(path)?)? })
+    }
+
+    #[staticmethod]
+    fn from_json(json: &str) -> PyResult<Stops> {
+        Ok(Stops { stops: parse(json.as_bytes())? })
+    }
+
+    /// decodes the compact binary encoding
+    #[staticmethod]
+    fn from_binary(data: &[u8]) -> PyResult<Stops> {
+        InterRegional::from_binary(data)
+            .map(|stops| Stops { stops })
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    fn save(&self, path: &str) -> PyResult<()> {
+        write(path, self.to_json()?.as_bytes())
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        to_json(&self.stops)
+    }
+
+    fn to_binary<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.stops.to_binary())
+    }
+
+    #[getter]
+    fn schema_version(&self) -> String {
+        self.stops.document.schema_version.clone()
+    }
+
+    /// creation date of the document in RFC 3339
+    #[getter]
+    fn date(&self) -> String {
+        self.stops.document.date.to_rfc3339()
+    }
+
+    /// the region ids, sorted
+    fn regions(&self) -> Vec<u32> {
+        let mut regions = self.stops.data.keys().copied().collect::<Vec<u32>>();
+        regions.sort_unstable();
+        regions
+    }
+
+    /// all reporting points of a region with their positions
+    fn positions(&self, region: u32) -> Option<HashMap<u32, Vec<Position>>> {
+        Some(self.stops.data.get(&region)?
+            .iter()
+            .map(|(reporting_point, list)| (*reporting_point, positions(list.clone())))
+            .collect())
+    }
+
+    fn look_up(&self, region: u32, reporting_point: u32) -> Option<Vec<Position>> {
+        self.stops.look_up(&region, &reporting_point).map(positions)
+    }
+
+    fn approximate_position(&self, region: u32, reporting_point: u32) -> Option<Position> {
+        self.stops.get_approximate_position(&region, &reporting_point).map(Position::from)
+    }
+
+    /// (reporting point, distance in meters, position) of the closest positions
+    #[pyo3(signature = (region, lat, lon, limit = 1))]
+    fn nearest(&self, region: u32, lat: f64, lon: f64, limit: usize) -> Option<Vec<(u32, f64, Position)>> {
+        Some(self.stops.nearest(&region, lat, lon, limit)?
+            .into_iter()
+            .map(|NearestPosition { reporting_point, distance, position }| (reporting_point, distance, position.into()))
+            .collect())
+    }
+
+    /// raises a ValueError if the stops or the graph are not consistent
+    #[pyo3(signature = (graph = None))]
+    fn validate(&self, graph: Option<PyRef<Graph>>) -> PyResult<()> {
+        self.stops.validate(graph.as_ref().map(|graph| &graph.graph))
+            .map_err(PyValueError::new_err)
+    }
+}
+
+/// the content of a graph.json
+#[pyclass(name = "Graph")]
+struct Graph {
+    graph: InterRegionalGraph,
+}
+
+impl Graph {
+    fn region(&self, region: u32) -> PyResult<&RegionGraph> {
+        self.graph.regions.get(&region)
+            .ok_or_else(|| PyKeyError::new_err(format!("no graph of region {}", region)))
+    }
+}
+
+#[pymethods]
+impl Graph {
+    #[staticmethod]
+    fn load(path: &str) -> PyResult<Graph> {
+        Ok(Graph { graph: parse(&read(path)?)? })
+    }
+
+    #[staticmethod]
+    fn from_json(json: &str) -> PyResult<Graph> {
+        Ok(Graph { graph: parse(json.as_bytes())? })
+    }
+
+    fn save(&self, path: &str) -> PyResult<()> {
+        write(path, self.to_json()?.as_bytes())
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        to_json(&self.graph)
+    }
+
+    /// the region ids, sorted
+    fn regions(&self) -> Vec<u32> {
+        let mut regions = self.graph.regions.keys().copied().collect::<Vec<u32>>();
+        regions.sort_unstable();
+        regions
+    }
+
+    /// all distinct reporting points that directly follow the given one
+    fn neighbours(&self, region: u32, reporting_point: u32) -> PyResult<Vec<u32>> {
+        Ok(self.region(region)?.neighbours(&reporting_point))
+    }
+
+    /// Graphviz DOT of a region, named after the stops
+    fn to_dot(&self, region: u32, stops: &Stops) -> PyResult<String> {
+        let positions = stops.stops.data.get(&region).cloned().unwrap_or_default();
+        Ok(self.region(region)?.to_dot(&positions))
+    }
+
+    /// GeoJSON FeatureCollection of a region as string
+    fn to_geojson(&self, region: u32, stops: &Stops) -> PyResult<String> {
+        let positions = stops.stops.data.get(&region).cloned().unwrap_or_default();
+        to_json(&self.region(region)?.to_geojson(&positions))
+    }
+}
+
+#[pymodule]
+pub fn stop_names(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add("SUPPORTED_SCHEMA_VERSION", SUPPORTED_SCHEMA_VERSION)?;
+    module.add_class::<Position>()?;
+    module.add_class::<Stops>()?;
+    module.add_class::<Graph>()?;
+    Ok(())
+}
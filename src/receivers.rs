@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// A station receiving R09 telegrams. Telegram dumps identify the receiver
+/// either by its ip or by its station id.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Receiver {
+    pub id: u32,
+    pub name: Option<String>,
+    pub ip: Option<String>,
+    pub station_id: Option<String>,
+    pub region: Option<u32>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+#[serde(transparent)]
+pub struct ReceiverRegistry {
+    pub receivers: Vec<Receiver>,
+}
+
+impl Receiver {
+    /// whether the ip or station id of a telegram belongs to this receiver
+    pub fn matches(&self, ip_or_station_id: &str) -> bool {
+        self.ip.as_deref() == Some(ip_or_station_id)
+            || self.station_id.as_deref() == Some(ip_or_station_id)
+    }
+}
+
+impl ReceiverRegistry {
+    pub fn find(&self, ip_or_station_id: &str) -> Option<&Receiver> {
+        self.receivers
+            .iter()
+            .find(|receiver| receiver.matches(ip_or_station_id))
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Receiver> {
+        self.receivers.iter().find(|receiver| receiver.id == id)
+    }
+}
@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+
+use alloc::collections::BinaryHeap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use hashbrown::{HashMap, HashSet};
+use crate::graph::{Edge, RegionGraph};
+
+/// travel time in seconds assumed for edges without a measured median, or
+/// with a negative or non finite one from a broken graph.json
+pub const UNKNOWN_TRAVEL_TIME: f64 = 60.0;
+
+/// what a route through the graph is optimized for
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Weight {
+    Hops,
+    ExpectedTime,
+}
+
+/// reporting points from start to end, the cost in hops or seconds
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Route {
+    pub junctions: Vec<u32>,
+    pub cost: f64,
+}
+
+impl Weight {
+    fn cost(&self, edge: &Edge) -> f64 {
+        match self {
+            Weight::Hops => 1.0,
+            Weight::ExpectedTime => edge.median_travel_time
+                .filter(|time| time.is_finite() && *time >= 0.0)
+                .unwrap_or(UNKNOWN_TRAVEL_TIME),
+        }
+    }
+}
+
+impl Route {
+    /// the reporting points strictly between start and end
+    pub fn between(&self) -> &[u32] {
+        match self.junctions.len() {
+            0..=2 => &[],
+            length => &self.junctions[1..length - 1],
+        }
+    }
+}
+
+/// entry of the dijkstra queue, the cheapest first
+#[derive(PartialEq)]
+struct Candidate {
+    cost: f64,
+    junction: u32,
+}
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+            .then_with(|| other.junction.cmp(&self.junction))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// junctions and edges a spur path of yen's algorithm must not use
+#[derive(Default)]
+struct Excluded {
+    junctions: HashSet<u32>,
+    edges: HashSet<(u32, u32)>,
+}
+
+impl RegionGraph {
+    /// Cheapest edge to every neighbour in any direction. With a line only
+    /// edges observed on that line are used, which excludes all edges of
+    /// single successor graphs.
+    fn steps(&self, junction: &u32, weight: Weight, line: Option<u16>) -> HashMap<u32, f64> {
+        let mut steps: HashMap<u32, f64> = HashMap::new();
+        for (_, edge) in self.edges(junction) {
+            if line.is_some_and(|line| !edge.lines.contains(&line)) {
+                continue;
+            }
+            let cost = weight.cost(edge);
+            steps.entry(edge.target)
+                .and_modify(|cheapest| *cheapest = cheapest.min(cost))
+                .or_insert(cost);
+        }
+        steps
+    }
+
+    /// cost of the cheapest edge between two reporting points
+    pub fn step_cost(&self, from: &u32, to: &u32, weight: Weight, line: Option<u16>) -> Option<f64> {
+        self.steps(from, weight, line).get(to).copied()
+    }
+
+    fn cost(&self, junctions: &[u32], weight: Weight, line: Option<u16>) -> Option<f64> {
+        junctions.windows(2)
+            .map(|pair| self.step_cost(&pair[0], &pair[1], weight, line))
+            .sum()
+    }
+
+    fn dijkstra(&self, from: u32, to: u32, weight: Weight, line: Option<u16>, excluded: &Excluded) -> Option<Route> {
+        let mut costs: HashMap<u32, f64> = HashMap::from([(from, 0.0)]);
+        let mut previous: HashMap<u32, u32> = HashMap::new();
+        let mut queue = BinaryHeap::from([Candidate { cost: 0.0, junction: from }]);
+
+        while let Some(Candidate { cost, junction }) = queue.pop() {
+            if junction == to {
+                let mut junctions = vec![to];
+                while let Some(before) = previous.get(junctions.last()?) {
+                    junctions.push(*before);
+                }
+                junctions.reverse();
+                return Some(Route { junctions, cost });
+            }
+            if cost > costs[&junction] {
+                continue;
+            }
+
+            for (next, step) in self.steps(&junction, weight, line) {
+                if excluded.junctions.contains(&next) || excluded.edges.contains(&(junction, next)) {
+                    continue;
+                }
+                let next_cost = cost + step;
+                if costs.get(&next).is_none_or(|known| next_cost < *known) {
+                    costs.insert(next, next_cost);
+                    previous.insert(next, junction);
+                    queue.push(Candidate { cost: next_cost, junction: next });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Cheapest route between two reporting points, optionally only over
+    /// edges observed on the given R09 line number.
+    pub fn shortest_path(&self, from: u32, to: u32, weight: Weight, line: Option<u16>) -> Option<Route> {
+        self.dijkstra(from, to, weight, line, &Excluded::default())
+    }
+
+    /// Up to `k` loopless routes between two reporting points, the cheapest
+    /// first (Yen's algorithm).
+    pub fn k_shortest_paths(&self, from: u32, to: u32, k: usize, weight: Weight, line: Option<u16>) -> Vec<Route> {
+        let mut routes = Vec::new();
+        if k == 0 {
+            return routes;
+        }
+        let Some(shortest) = self.shortest_path(from, to, weight, line) else {
+            return routes;
+        };
+        routes.push(shortest);
+        let mut candidates: Vec<Route> = Vec::new();
+
+        while routes.len() < k {
+            let last = &routes[routes.len() - 1].junctions;
+            for spur in 0..last.len() - 1 {
+                let root = &last[..=spur];
+                let mut excluded = Excluded {
+                    junctions: root[..spur].iter().copied().collect(),
+                    ..Default::default()
+                };
+                for route in &routes {
+                    if route.junctions.len() > spur + 1 && route.junctions.starts_with(root) {
+                        excluded.edges.insert((route.junctions[spur], route.junctions[spur + 1]));
+                    }
+                }
+
+                let Some(spur_route) = self.dijkstra(last[spur], to, weight, line, &excluded) else {
+                    continue;
+                };
+                let Some(root_cost) = self.cost(root, weight, line) else {
+                    continue;
+                };
+                let mut junctions = root[..spur].to_vec();
+                junctions.extend(spur_route.junctions);
+                if !candidates.iter().any(|candidate| candidate.junctions == junctions) {
+                    candidates.push(Route { junctions, cost: root_cost + spur_route.cost });
+                }
+            }
+
+            let Some(cheapest) = candidates.iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.cost.total_cmp(&b.cost)
+                    .then_with(|| a.junctions.len().cmp(&b.junctions.len()))
+                    .then_with(|| a.junctions.cmp(&b.junctions)))
+                .map(|(i, _)| i) else {
+                break;
+            };
+            routes.push(candidates.swap_remove(cheapest));
+        }
+
+        routes
+    }
+
+    /// The reporting points a vehicle passes between two stops, along the
+    /// route with the fewest hops.
+    pub fn reporting_points_between(&self, from: u32, to: u32, line: Option<u16>) -> Option<Vec<u32>> {
+        self.shortest_path(from, to, Weight::Hops, line)
+            .map(|route| route.between().to_vec())
+    }
+}
@@ -1,3 +1,5 @@
+use crate::{line_colour, BinaryError, CompactStops, Edge, HashMap, InterRegional, InterRegionalGraph, Line, LineReferences, PositionStatus, ReceiverRegistry, RegionGraph, Route, TelegramType, TransmissionPosition, Weight};
+
 
 #[test]
 fn test_serialization() {
@@ -6,17 +8,20 @@ fn test_serialization() {
         name: Some("name".to_string()),
         telegram_type: TelegramType::DoorClosed,
         direction: 0,
-        lat: 0.0,
-        lon: 0.0
+        lat: Some(0.0),
+        lon: Some(0.0),
+        status: PositionStatus::Surveyed,
+        accuracy: None,
     };
 
     let reference = String::from("{
   \"dhid\": \"dhid\",
   \"name\": \"name\",
-  \"telegram_type\": \"3\",
+  \"telegram_type\": 3,
   \"direction\": 0,
   \"lat\": 0.0,
-  \"lon\": 0.0
+  \"lon\": 0.0,
+  \"status\": \"surveyed\"
 }");
     let json_data = serde_json::to_string_pretty(&data)
         .expect("cannot serialize structs!");
@@ -24,3 +29,383 @@ fn test_serialization() {
     assert_eq!(json_data, reference);
 }
 
+#[test]
+fn test_position_status() {
+    // positions without status are surveyed if they have coordinates
+    let position: TransmissionPosition = serde_json::from_str(
+        "{\"DHID\": null, \"name\": \"a\", \"telegram_type\": 3, \"direction\": 0, \"lat\": 51.0, \"lon\": 13.7}"
+    ).unwrap();
+    assert_eq!(position.status, PositionStatus::Surveyed);
+    assert_eq!(position.coordinates(), Some((51.0, 13.7)));
+
+    let position: TransmissionPosition = serde_json::from_str(
+        "{\"dhid\": null, \"name\": \"b\", \"telegram_type\": 3, \"direction\": 0}"
+    ).unwrap();
+    assert_eq!(position.status, PositionStatus::Unknown);
+    assert_eq!(position.coordinates(), None);
+
+    let position: TransmissionPosition = serde_json::from_str(
+        "{\"dhid\": null, \"name\": \"c\", \"telegram_type\": 3, \"direction\": 0,
+          \"lat\": 51.0, \"lon\": 13.7, \"status\": \"estimated_from_osm\", \"accuracy\": 25.0}"
+    ).unwrap();
+    assert_eq!(position.status, PositionStatus::EstimatedFromOsm);
+    assert_eq!(position.accuracy, Some(25.0));
+    assert_eq!(serde_json::to_value(&position).unwrap()["accuracy"], 25.0);
+
+    // the lookup prefers positions with coordinates and shows the status
+    let mut stops = InterRegional::from(concat!(env!("CARGO_MANIFEST_DIR"), "/stops.json"))
+        .expect("cannot read stops.json");
+    let unknown = TransmissionPosition {
+        lat: None,
+        lon: None,
+        status: PositionStatus::Unknown,
+        ..position.clone()
+    };
+    stops.data.get_mut(&0).unwrap().insert(1, vec![unknown.clone()]);
+    stops.data.get_mut(&0).unwrap().get_mut(&504).unwrap().insert(0, unknown.clone());
+    assert_eq!(stops.get_approximate_position(&0, &1).map(|position| position.status), Some(PositionStatus::Unknown));
+    assert_eq!(stops.get_approximate_position(&0, &504).and_then(|position| position.name).as_deref(), Some("Reichenbachstraße"));
+    assert_eq!(stops.validate(None), Ok(()));
+
+    stops.data.get_mut(&0).unwrap().insert(2, vec![TransmissionPosition { status: PositionStatus::Inferred, ..unknown.clone() }]);
+    assert!(stops.validate(None).unwrap_err().contains("has no coordinates"));
+    stops.data.get_mut(&0).unwrap().insert(2, vec![TransmissionPosition { status: PositionStatus::Unknown, ..position.clone() }]);
+    assert!(stops.validate(None).unwrap_err().contains("has coordinates but is Unknown"));
+    stops.data.get_mut(&0).unwrap().insert(2, vec![TransmissionPosition { accuracy: Some(-1.0), ..position }]);
+    assert!(stops.validate(None).unwrap_err().contains("accuracy"));
+}
+
+
+#[test]
+fn test_graph_loading() {
+    let graph = InterRegionalGraph::from("graph.json")
+        .expect("cannot load graph.json");
+    let region = graph.extract(&0)
+        .expect("region 0 missing");
+
+    // graph.json still has a single successor per direction
+    assert_eq!(region.neighbours(&281), vec![231, 282]);
+    assert_eq!(region.most_likely_successor(&281, &3), Some(231));
+
+    // every successor with counts, travel times and lines
+    let region = RegionGraph {
+        structure: HashMap::from([(1, HashMap::from([(0, vec![
+            Edge { target: 2, count: 1, median_travel_time: Some(40.0), lines: vec![3] },
+            Edge { target: 3, count: 5, median_travel_time: Some(60.0), lines: vec![3, 7] },
+        ])]))]),
+    };
+    let json = serde_json::to_string(&region).expect("cannot serialize graph");
+    let region: RegionGraph = serde_json::from_str(&json).expect("cannot deserialize graph");
+    assert_eq!(region.most_likely_successor(&1, &0), Some(3));
+    assert_eq!(region.neighbours(&1), vec![2, 3]);
+    assert_eq!(region.most_likely_successor(&1, &1), None);
+}
+
+#[test]
+fn test_line_references() {
+    let lines = LineReferences::from([(93, "E8".to_string()), (3, "3".to_string())]);
+
+    let replacement = Line::from_reference("E8", &lines).expect("unknown line");
+    assert_eq!(replacement.number, 93);
+    assert_eq!(replacement, Line::new(93));
+    assert_eq!(Line::from_number(93, &lines).to_string(), "E8");
+
+    assert_eq!(Line::from_reference("11", &lines), Some(Line::new(11)));
+    assert_eq!(Line::from_reference("EV3", &lines), None);
+    assert_eq!(Line::new(11).to_string(), "11");
+
+    let line: Line = serde_json::from_str("{\"number\": 93, \"reference\": \"E8\"}").unwrap();
+    assert_eq!(line.reference.as_deref(), Some("E8"));
+    let line: Line = serde_json::from_str("93").unwrap();
+    assert_eq!(line.reference, None);
+}
+
+#[test]
+fn test_receiver_registry() {
+    let registry = ReceiverRegistry::from("receivers.json").expect("cannot read receivers.json");
+
+    let receiver = registry.find("10.13.37.101").expect("unknown receiver");
+    assert_eq!(receiver.id, 1);
+    assert_eq!(registry.get(1), Some(receiver));
+    assert!(registry.find("10.13.37.102").is_none());
+}
+
+fn edge(target: u32, median_travel_time: f64, line: u16) -> Edge {
+    Edge { target, count: 1, median_travel_time: Some(median_travel_time), lines: vec![line] }
+}
+
+#[test]
+fn test_routing() {
+    // 1 -> 2 -> 3 and 1 -> 4 -> 3 take two hops, 1 -> 5 -> 6 -> 3 is the fastest
+    let graph = RegionGraph {
+        structure: HashMap::from([
+            (1, HashMap::from([
+                (0, vec![edge(2, 60.0, 3), edge(5, 10.0, 3)]),
+                (1, vec![edge(4, 30.0, 7)]),
+            ])),
+            (2, HashMap::from([(0, vec![edge(3, 60.0, 3)])])),
+            (4, HashMap::from([(1, vec![edge(3, 100.0, 7)])])),
+            (5, HashMap::from([(0, vec![edge(6, 10.0, 3)])])),
+            (6, HashMap::from([(0, vec![edge(3, 10.0, 3)])])),
+        ]),
+    };
+
+    let route = graph.shortest_path(1, 3, Weight::Hops, None).expect("no route");
+    assert_eq!(route, Route { junctions: vec![1, 2, 3], cost: 2.0 });
+    let route = graph.shortest_path(1, 3, Weight::ExpectedTime, None).expect("no route");
+    assert_eq!(route, Route { junctions: vec![1, 5, 6, 3], cost: 30.0 });
+    let route = graph.shortest_path(1, 3, Weight::ExpectedTime, Some(7)).expect("no route");
+    assert_eq!(route.junctions, vec![1, 4, 3]);
+    assert_eq!(graph.shortest_path(3, 1, Weight::Hops, None), None);
+    assert_eq!(graph.shortest_path(1, 1, Weight::Hops, None).expect("no route").between(), &[] as &[u32]);
+
+    let routes = graph.k_shortest_paths(1, 3, 5, Weight::ExpectedTime, None);
+    assert_eq!(routes.iter().map(|route| route.junctions.clone()).collect::<Vec<_>>(), vec![
+        vec![1, 5, 6, 3],
+        vec![1, 2, 3],
+        vec![1, 4, 3],
+    ]);
+    assert_eq!(routes.iter().map(|route| route.cost).collect::<Vec<_>>(), vec![30.0, 120.0, 130.0]);
+    assert_eq!(graph.k_shortest_paths(1, 3, 5, Weight::Hops, Some(3)).len(), 2);
+    assert!(graph.k_shortest_paths(1, 3, 0, Weight::Hops, None).is_empty());
+
+    assert_eq!(graph.reporting_points_between(1, 3, Some(7)), Some(vec![4]));
+    assert_eq!(graph.reporting_points_between(1, 3, Some(9)), None);
+}
+
+#[test]
+fn test_graph_export() {
+    let stop = |name: &str, lat: f64, lon: f64| TransmissionPosition {
+        dhid: None,
+        name: Some(name.to_string()),
+        telegram_type: TelegramType::DoorClosed,
+        direction: 0,
+        lat: Some(lat),
+        lon: Some(lon),
+        status: PositionStatus::Surveyed,
+        accuracy: None,
+    };
+    let positions = HashMap::from([
+        (1, vec![stop("Postplatz", 51.05, 13.73)]),
+        (2, vec![stop("\"Altmarkt\"", 51.04, 13.74)]),
+        (3, vec![TransmissionPosition { lat: None, lon: None, status: PositionStatus::Unknown, ..stop("Walpurgisstraße", 51.0, 13.7) }]),
+    ]);
+    let graph = RegionGraph {
+        structure: HashMap::from([
+            (1, HashMap::from([(2, vec![edge(2, 60.0, 3), edge(3, 60.0, 7)])])),
+            (2, HashMap::from([(1, vec![Edge { lines: vec![3, 8], ..edge(1, 60.0, 3) }])])),
+        ]),
+    };
+
+    let dot = graph.to_dot(&positions);
+    assert!(dot.starts_with("digraph region {"));
+    assert!(dot.contains("\"1\" [label=\"Postplatz\\n1\"];"));
+    assert!(dot.contains("\\\"Altmarkt\\\""));
+    assert!(dot.contains("\"1\" -> \"2\" [label=\"2 (1)\"];"));
+    // the location of 3 is unknown
+    assert!(dot.contains("\"3\" [label=\"Walpurgisstraße\\n3\"];"));
+    assert!(dot.contains("\"1\" -> \"3\" [label=\"2 (1)\", color=red, style=dashed];"));
+
+    let geojson = graph.to_geojson(&positions);
+    let features = geojson["features"].as_array().expect("no features");
+    assert_eq!(features.len(), 4);
+    assert_eq!(features[0]["geometry"]["coordinates"][0][0], 13.73);
+    assert_eq!(features[0]["properties"]["stroke"], line_colour(3));
+    assert_eq!(features[1]["properties"]["missing_coordinates"], true);
+    assert!(features[1]["geometry"].is_null());
+    // one LineString per line using the edge
+    assert_eq!(features[3]["properties"]["line"], 8);
+    assert_ne!(features[2]["properties"]["stroke"], features[3]["properties"]["stroke"]);
+}
+
+#[cfg(feature = "database")]
+#[test]
+fn test_stop_database() {
+    use crate::{InterRegional, StopDatabase};
+    use std::time::{Duration, Instant};
+
+    let directory = std::env::temp_dir().join(format!("stop-database-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).expect("cannot create directory");
+    let stops = directory.join("stops.json");
+    let graph = directory.join("graph.json");
+    std::fs::copy("stops.json", &stops).expect("cannot copy stops.json");
+    std::fs::copy("graph.json", &graph).expect("cannot copy graph.json");
+
+    let database = StopDatabase::open(&stops, Some(&graph)).expect("cannot open database");
+    assert_eq!(database.schema_version(), "1.0");
+    assert_eq!(database.date().to_rfc3339(), "2022-07-10T00:05:01.012169209+00:00");
+    let before = database.snapshot();
+
+    let mut data = InterRegional::from("stops.json").expect("cannot read stops.json");
+    data.document.schema_version = "1.1".to_string();
+    data.write(stops.to_str().unwrap());
+    database.reload().expect("cannot reload");
+    assert_eq!(database.schema_version(), "1.1");
+    assert_ne!(database.snapshot().stops_checksum, before.stops_checksum);
+    // readers keep the snapshot they got
+    assert_eq!(before.schema_version(), "1.0");
+
+    // invalid versions are not swapped in
+    data.document.schema_version = "2.0".to_string();
+    data.write(stops.to_str().unwrap());
+    assert!(database.reload().is_err());
+    assert_eq!(database.schema_version(), "1.1");
+    assert!(database.last_error().is_some_and(|e| e.contains("2.0")));
+
+    database.watch().expect("cannot watch files");
+    data.document.schema_version = "1.2".to_string();
+    data.write(stops.to_str().unwrap());
+    let start = Instant::now();
+    while database.schema_version() != "1.2" && start.elapsed() < Duration::from_secs(10) {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(database.schema_version(), "1.2");
+    assert_eq!(database.last_error(), None);
+
+    std::fs::remove_dir_all(&directory).ok();
+}
+
+#[test]
+fn test_binary_encoding() {
+    let stops = InterRegional::from(concat!(env!("CARGO_MANIFEST_DIR"), "/stops.json"))
+        .expect("cannot read stops.json");
+    let binary = stops.to_binary();
+    assert!(binary.len() < serde_json::to_vec(&stops).unwrap().len() / 2);
+    assert_eq!(InterRegional::from_binary(&binary), Ok(stops.clone()));
+
+    let compact = CompactStops::new(&binary).expect("cannot open compact stops");
+    for (region, positions) in &stops.data {
+        for reporting_point in positions.keys() {
+            assert_eq!(
+                compact.look_up(region, reporting_point),
+                Ok(stops.look_up(region, reporting_point)),
+            );
+        }
+    }
+    assert_eq!(compact.look_up(&0, &u32::MAX), Ok(None));
+    assert_eq!(compact.look_up(&u32::MAX, &0), Ok(None));
+
+    // coordinates with more than seven decimals are kept as they are,
+    let mut precise = stops.clone();
+    let (region, positions) = precise.data.iter_mut().next().unwrap();
+    let region = *region;
+    let (reporting_point, position) = positions.iter_mut().next().unwrap();
+    let reporting_point = *reporting_point;
+    position[0].lat = Some(51.049_259_123_456_78);
+    position[0].name = None;
+    // as well as missing coordinates, status and accuracy
+    position.push(TransmissionPosition { lat: None, lon: None, status: PositionStatus::Unknown, ..position[0].clone() });
+    position.push(TransmissionPosition { lon: None, accuracy: Some(12.5), status: PositionStatus::Inferred, ..position[0].clone() });
+    position.push(TransmissionPosition { accuracy: Some(30.0), status: PositionStatus::EstimatedFromOsm, ..position[0].clone() });
+    let binary = precise.to_binary();
+    assert_eq!(InterRegional::from_binary(&binary), Ok(precise.clone()));
+    assert_eq!(
+        CompactStops::new(&binary).unwrap().look_up(&region, &reporting_point),
+        Ok(precise.look_up(&region, &reporting_point)),
+    );
+
+    // counts beyond u16
+    let mut crowded = precise.clone();
+    let position = crowded.data.get_mut(&region).unwrap().get_mut(&reporting_point).unwrap();
+    *position = vec![position[0].clone(); 70_000];
+    assert_eq!(InterRegional::from_binary(&crowded.to_binary()), Ok(crowded));
+
+    assert_eq!(InterRegional::from_binary(b"{}"), Err(BinaryError::BadMagic));
+    assert_eq!(InterRegional::from_binary(&binary[..binary.len() - 3]), Err(BinaryError::Truncated));
+
+    // crafted offsets and counts are errors instead of overflows
+    let u32s = |values: &[u32]| values.iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<u8>>();
+    let rejected = |result: Result<CompactStops, BinaryError>| matches!(result, Err(BinaryError::Truncated | BinaryError::Invalid(_)));
+    assert!(rejected(CompactStops::new(&[b"SNS3".as_slice(), &u32s(&[u32::MAX])].concat())));
+    assert!(rejected(CompactStops::new(&[b"SNS3".as_slice(), &u32s(&[1, 0, u32::MAX, 16])].concat())));
+    // no strings, an empty document and meta, then a single region 0 whose
+    // index starts at `region[0]`
+    let crafted = |region: &[u32]| [b"SNS3".as_slice(), &u32s(&[0, 0, 16]), &[0; 28], &u32s(&[1, 0]), &u32s(region)].concat();
+    for region in [vec![u32::MAX], vec![40, u32::MAX], vec![40, 1, 0, u32::MAX, u32::MAX]] {
+        let data = crafted(&region);
+        let compact = CompactStops::new(&data).expect("cannot open crafted stops");
+        assert_eq!(compact.regions(), Ok(vec![0]));
+        assert!(compact.look_up(&0, &0).is_err());
+        assert!(compact.decode().is_err());
+    }
+    assert_eq!(InterRegionalGraph::from_binary(&[b"SNG2".as_slice(), &u32s(&[u32::MAX, 0, u32::MAX])].concat()), Err(BinaryError::Truncated));
+
+    let graph = InterRegionalGraph::from(concat!(env!("CARGO_MANIFEST_DIR"), "/graph.json"))
+        .expect("cannot read graph.json");
+    assert_eq!(InterRegionalGraph::from_binary(&graph.to_binary()), Ok(graph));
+
+    let graph = InterRegionalGraph {
+        regions: HashMap::from([(1, RegionGraph {
+            structure: HashMap::from([(1, HashMap::from([(0, vec![
+                Edge { target: 2, count: 1, median_travel_time: Some(40.5), lines: vec![3] },
+                Edge { target: 3, count: 5, median_travel_time: None, lines: vec![] },
+            ])]))]),
+        })]),
+    };
+    assert_eq!(InterRegionalGraph::from_binary(&graph.to_binary()), Ok(graph));
+
+    // every direction and more lines than fit into a u16
+    let graph = InterRegionalGraph {
+        regions: HashMap::from([(1, RegionGraph {
+            structure: HashMap::from([(1, (0..=u8::MAX)
+                .map(|direction| (direction, vec![Edge {
+                    target: 2,
+                    count: 1,
+                    median_travel_time: None,
+                    lines: vec![3; if direction == 0 { 70_000 } else { 1 }],
+                }]))
+                .collect())]),
+        })]),
+    };
+    assert_eq!(InterRegionalGraph::from_binary(&graph.to_binary()), Ok(graph));
+}
+
+#[cfg(feature = "python")]
+#[test]
+fn test_python_bindings() {
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    Python::initialize();
+    Python::attach(|py| {
+        let locals = PyDict::new(py);
+        locals.set_item("stop_names", pyo3::wrap_pymodule!(crate::python::stop_names)(py)).unwrap();
+        locals.set_item("stops_path", concat!(env!("CARGO_MANIFEST_DIR"), "/stops.json")).unwrap();
+        locals.set_item("graph_path", concat!(env!("CARGO_MANIFEST_DIR"), "/graph.json")).unwrap();
+        py.run(c"
+import json
+
+stops = stop_names.Stops.load(stops_path)
+assert stops.schema_version.startswith(stop_names.SUPPORTED_SCHEMA_VERSION)
+assert 0 in stops.regions()
+
+position = stops.approximate_position(0, 504)
+assert position.name == 'Reichenbachstraße' and position.telegram_type == 3
+assert position.status == 'surveyed' and position.accuracy is None
+assert stops.look_up(0, 504)[0].lat == position.lat
+assert stops.look_up(0, 4294967295) is None
+assert 504 in stops.positions(0)
+
+reporting_point, distance, nearest = stops.nearest(0, position.lat, position.lon)[0]
+assert distance == 0.0 and nearest.lon == position.lon
+assert len(stops.nearest(0, position.lat, position.lon, limit=3)) == 3
+assert stops.nearest(4294967295, 0.0, 0.0) is None
+
+graph = stop_names.Graph.load(graph_path)
+stops.validate()
+stops.validate(graph)
+assert graph.neighbours(0, 281) == [231, 282]
+assert graph.to_dot(0, stops).startswith('digraph')
+assert json.loads(graph.to_geojson(0, stops))['type'] == 'FeatureCollection'
+
+assert stop_names.Stops.from_json(stops.to_json()).regions() == stops.regions()
+assert stop_names.Stops.from_binary(stops.to_binary()).look_up(0, 504)[0].name == position.name
+
+try:
+    stop_names.Stops.from_json('{}')
+    raise AssertionError('parsed an empty document')
+except ValueError:
+    pass
+", None, Some(&locals)).unwrap();
+    });
+}
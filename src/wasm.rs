@@ -0,0 +1,59 @@
+//! wasm-bindgen bindings for lookups in the browser. Positions are handed
+//! out as plain objects in the json format of stops.json.
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+
+use wasm_bindgen::prelude::*;
+
+use crate::InterRegional;
+
+/// the stops of all regions
+#[wasm_bindgen]
+pub struct Stops {
+    stops: InterRegional,
+}
+
+fn error(e: impl fmt::Display) -> JsError {
+    JsError::new(&e.to_string())
+}
+
+#[wasm_bindgen]
+impl Stops {
+    /// parses the content of a stops.json
+    #[wasm_bindgen(constructor)]
+    pub fn new(json: &str) -> Result<Stops, JsError> {
+        Ok(Stops {
+            stops: serde_json::from_str(json).map_err(error)?,
+        })
+    }
+
+    /// decodes a file written by `InterRegional::to_binary`
+    #[wasm_bindgen(js_name = fromBinary)]
+    pub fn from_binary(data: &[u8]) -> Result<Stops, JsError> {
+        Ok(Stops {
+            stops: InterRegional::from_binary(data).map_err(error)?,
+        })
+    }
+
+    /// the region ids, sorted
+    pub fn regions(&self) -> Vec<u32> {
+        let mut regions = self.stops.data.keys().copied().collect::<Vec<u32>>();
+        regions.sort_unstable();
+        regions
+    }
+
+    /// all positions of a reporting point, undefined if it is unknown
+    #[wasm_bindgen(js_name = lookUp)]
+    pub fn look_up(&self, region: u32, reporting_point: u32) -> Result<JsValue, JsError> {
+        serde_wasm_bindgen::to_value(&self.stops.look_up(&region, &reporting_point))
+            .map_err(error)
+    }
+
+    /// the position used for markers, see `get_approximate_position`
+    #[wasm_bindgen(js_name = approximatePosition)]
+    pub fn approximate_position(&self, region: u32, reporting_point: u32) -> Result<JsValue, JsError> {
+        serde_wasm_bindgen::to_value(&self.stops.get_approximate_position(&region, &reporting_point))
+            .map_err(error)
+    }
+}
@@ -0,0 +1,33 @@
+use std::error::Error;
+use clap::Parser;
+use stop_names::StopDatabase;
+use stopserver::routes::router;
+
+/// Serves stops.json and graph.json over http and reloads them when they
+/// change.
+#[derive(Debug, Parser)]
+#[command(name = "stopserver", version)]
+struct Cli {
+    /// stops.json to serve
+    #[arg(long, default_value = "stops.json")]
+    stops: String,
+    /// graph.json to serve on /graph
+    #[arg(long)]
+    graph: Option<String>,
+    /// address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+    let database = StopDatabase::open(&cli.stops, cli.graph.as_ref())?;
+    database.watch()?;
+    println!("serving stops.json {} from {}", database.schema_version(), database.date());
+
+    let listener = tokio::net::TcpListener::bind(&cli.listen).await?;
+    println!("listening on {}", cli.listen);
+    axum::serve(listener, router(database)).await?;
+    Ok(())
+}
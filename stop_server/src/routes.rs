@@ -0,0 +1,176 @@
+//! The http endpoints. Every response carries the ETag of the file it was
+//! computed from and requests with a matching `If-None-Match` get a 304.
+//! The ETags are weak: the bodies are serialized from hash maps, so the
+//! same file does not give the same bytes every time.
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use stop_names::{RegionMetaInformation, StopDatabase, TransmissionPosition};
+
+#[derive(Debug, Serialize)]
+struct RegionSummary {
+    id: u32,
+    meta: Option<RegionMetaInformation>,
+    reporting_points: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupQuery {
+    region: u32,
+    reporting_point: u32,
+    /// R09 telegram type, 0 to 3
+    #[serde(rename = "type")]
+    telegram_type: Option<u8>,
+    direction: Option<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NearestQuery {
+    region: u32,
+    lat: f64,
+    lon: f64,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    q: String,
+    region: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResult {
+    region: u32,
+    reporting_point: u32,
+    position: TransmissionPosition,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQuery {
+    region: Option<u32>,
+}
+
+pub fn router(database: StopDatabase) -> Router {
+    Router::new()
+        .route("/regions", get(regions))
+        .route("/regions/{id}/stops", get(region_stops))
+        .route("/lookup", get(lookup))
+        .route("/nearest", get(nearest))
+        .route("/search", get(search))
+        .route("/graph", get(graph))
+        .with_state(database)
+}
+
+/// 304 if the client has the current version, the json body otherwise.
+/// `If-None-Match` uses the weak comparison, the `W/` prefix is ignored.
+fn cached<T: Serialize>(headers: &HeaderMap, etag: &str, body: T) -> Response {
+    fn opaque(tag: &str) -> &str {
+        tag.strip_prefix("W/").unwrap_or(tag)
+    }
+    let fresh = headers.get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || opaque(candidate) == opaque(etag));
+
+    if fresh {
+        (StatusCode::NOT_MODIFIED, [(header::ETAG, etag.to_string())]).into_response()
+    } else {
+        ([(header::ETAG, etag.to_string())], Json(body)).into_response()
+    }
+}
+
+fn etag(checksum: u64) -> String {
+    format!("W/\"{:016x}\"", checksum)
+}
+
+fn not_found(message: String) -> Response {
+    (StatusCode::NOT_FOUND, message).into_response()
+}
+
+async fn regions(State(database): State<StopDatabase>, headers: HeaderMap) -> Response {
+    let snapshot = database.snapshot();
+    let stops = &snapshot.stops;
+    let mut regions = stops.data.iter()
+        .map(|(id, positions)| RegionSummary {
+            id: *id,
+            meta: stops.meta.get(id).cloned(),
+            reporting_points: positions.len(),
+        })
+        .collect::<Vec<_>>();
+    regions.sort_by_key(|region| region.id);
+    cached(&headers, &etag(snapshot.stops_checksum), regions)
+}
+
+async fn region_stops(State(database): State<StopDatabase>, Path(id): Path<u32>, headers: HeaderMap) -> Response {
+    let snapshot = database.snapshot();
+    match snapshot.stops.data.get(&id) {
+        Some(positions) => cached(&headers, &etag(snapshot.stops_checksum), positions),
+        None => not_found(format!("no region {}", id)),
+    }
+}
+
+async fn lookup(State(database): State<StopDatabase>, Query(query): Query<LookupQuery>, headers: HeaderMap) -> Response {
+    let snapshot = database.snapshot();
+    let positions = snapshot.stops.look_up(&query.region, &query.reporting_point)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|position| query.telegram_type.is_none_or(|telegram_type| position.telegram_type.clone() as u8 == telegram_type))
+        .filter(|position| query.direction.is_none_or(|direction| position.direction == direction))
+        .collect::<Vec<_>>();
+
+    if positions.is_empty() {
+        return not_found(format!("no position of reporting point {} in region {}", query.reporting_point, query.region));
+    }
+    cached(&headers, &etag(snapshot.stops_checksum), positions)
+}
+
+async fn nearest(State(database): State<StopDatabase>, Query(query): Query<NearestQuery>, headers: HeaderMap) -> Response {
+    let snapshot = database.snapshot();
+    match snapshot.stops.nearest(&query.region, query.lat, query.lon, query.limit.unwrap_or(1)) {
+        Some(nearest) => cached(&headers, &etag(snapshot.stops_checksum), nearest),
+        None => not_found(format!("no region {}", query.region)),
+    }
+}
+
+async fn search(State(database): State<StopDatabase>, Query(query): Query<SearchQuery>, headers: HeaderMap) -> Response {
+    let needle = query.q.trim().to_lowercase();
+    if needle.is_empty() {
+        return (StatusCode::BAD_REQUEST, "q must not be empty").into_response();
+    }
+
+    let snapshot = database.snapshot();
+    let mut results = snapshot.stops.data.iter()
+        .filter(|(region, _)| query.region.is_none_or(|filter| filter == **region))
+        .flat_map(|(region, positions)| positions.iter().flat_map(move |(reporting_point, positions)| {
+            positions.iter().map(move |position| (*region, *reporting_point, position))
+        }))
+        .filter(|(_, _, position)| position.name.as_ref().is_some_and(|name| name.to_lowercase().contains(&needle)))
+        .map(|(region, reporting_point, position)| SearchResult {
+            region,
+            reporting_point,
+            position: position.clone(),
+        })
+        .collect::<Vec<_>>();
+    results.sort_by_key(|result| (result.region, result.reporting_point));
+    cached(&headers, &etag(snapshot.stops_checksum), results)
+}
+
+async fn graph(State(database): State<StopDatabase>, Query(query): Query<GraphQuery>, headers: HeaderMap) -> Response {
+    let snapshot = database.snapshot();
+    let (Some(graph), Some(checksum)) = (&snapshot.graph, snapshot.graph_checksum) else {
+        return not_found("the server has no graph".to_string());
+    };
+
+    match query.region {
+        None => cached(&headers, &etag(checksum), graph),
+        Some(region) => match graph.extract(&region) {
+            Some(region_graph) => cached(&headers, &etag(checksum), region_graph),
+            None => not_found(format!("no graph of region {}", region)),
+        },
+    }
+}
@@ -0,0 +1,100 @@
+use std::fs;
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use serde_json::Value;
+use stop_names::{InterRegional, StopDatabase};
+use tower::ServiceExt;
+use crate::routes::router;
+
+const STOPS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../stops.json");
+const GRAPH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../graph.json");
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("cannot start runtime")
+}
+
+/// status, etag and json body of a request
+fn get(router: &Router, uri: &str, etag: Option<&str>) -> (StatusCode, Option<String>, Value) {
+    let mut request = Request::get(uri);
+    if let Some(etag) = etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    runtime().block_on(async {
+        let response = router.clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .expect("request failed");
+        let status = response.status();
+        let etag = response.headers().get(header::ETAG)
+            .map(|etag| etag.to_str().unwrap().to_string());
+        let body = to_bytes(response.into_body(), usize::MAX).await.expect("cannot read body");
+        (status, etag, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    })
+}
+
+#[test]
+fn test_endpoints() {
+    let router = router(StopDatabase::open(STOPS, Some(GRAPH)).expect("cannot open database"));
+
+    let (status, etag, regions) = get(&router, "/regions", None);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(regions[0]["id"], 0);
+    let etag = etag.expect("no etag");
+    assert!(etag.starts_with("W/\""));
+    assert_eq!(get(&router, "/regions", Some(&etag)).0, StatusCode::NOT_MODIFIED);
+    // weak comparison, a client may drop the W/
+    assert_eq!(get(&router, "/regions", Some(&etag[2..])).0, StatusCode::NOT_MODIFIED);
+    assert_eq!(get(&router, "/regions", Some("\"outdated\"")).0, StatusCode::OK);
+
+    let (status, _, stops) = get(&router, "/regions/0/stops", None);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stops["1258"][0]["name"], "Waldschloesschenstrasse");
+    assert_eq!(get(&router, "/regions/7/stops", None).0, StatusCode::NOT_FOUND);
+
+    let (status, _, positions) = get(&router, "/lookup?region=0&reporting_point=1258&type=3&direction=0", None);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(positions[0]["lat"], 51.069858);
+    assert_eq!(positions[0]["status"], "surveyed");
+    assert_eq!(get(&router, "/lookup?region=0&reporting_point=1258&type=0", None).0, StatusCode::NOT_FOUND);
+
+    let (_, _, nearest) = get(&router, "/nearest?region=0&lat=51.069858&lon=13.775674&limit=3", None);
+    assert_eq!(nearest.as_array().map(Vec::len), Some(3));
+    assert_eq!(nearest[0]["position"]["name"], "Waldschloesschenstrasse");
+    assert_eq!(nearest[0]["distance"], 0.0);
+
+    let (_, _, results) = get(&router, "/search?q=waldschl", None);
+    assert!(results.as_array().expect("no results").iter().any(|result| result["reporting_point"] == 1258));
+    assert_eq!(get(&router, "/search?q=%20", None).0, StatusCode::BAD_REQUEST);
+
+    let (status, graph_etag, graph) = get(&router, "/graph?region=0", None);
+    assert_eq!(status, StatusCode::OK);
+    assert!(graph["structure"]["281"].is_object());
+    assert_ne!(graph_etag, Some(etag));
+    assert_eq!(get(&router, "/graph?region=7", None).0, StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_reload() {
+    let path = std::env::temp_dir().join(format!("stopserver-reload-{}.json", std::process::id()));
+    fs::copy(STOPS, &path).expect("cannot copy stops.json");
+    let database = StopDatabase::open(&path, None::<&str>).expect("cannot open database");
+    let router = router(database.clone());
+    let (_, etag, _) = get(&router, "/lookup?region=0&reporting_point=1258", None);
+    let etag = etag.expect("no etag");
+    assert_eq!(get(&router, "/graph", None).0, StatusCode::NOT_FOUND);
+
+    let mut stops = InterRegional::from(STOPS).expect("cannot read stops.json");
+    stops.data.get_mut(&0).unwrap().get_mut(&1258).unwrap()[0].name = Some("Renamed".to_string());
+    stops.write(path.to_str().unwrap());
+    database.reload().expect("cannot reload");
+
+    // the cached version is outdated
+    let (status, new_etag, positions) = get(&router, "/lookup?region=0&reporting_point=1258", Some(&etag));
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(new_etag, Some(etag));
+    assert_eq!(positions[0]["name"], "Renamed");
+    fs::remove_file(&path).ok();
+}